@@ -3,7 +3,7 @@
 
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 use crate::defs;
 
@@ -42,6 +42,91 @@ pub enum Commands {
         payload: String,
     },
     Modules,
-    Conflicts,
-    Diagnostics,
+    Conflicts {
+        #[arg(long)]
+        module: Option<String>,
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    Diagnostics {
+        #[arg(long)]
+        module: Option<String>,
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    Lint {
+        target: String,
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    Impact {
+        target: Option<String>,
+        #[arg(long)]
+        summary: bool,
+    },
+    Capabilities,
+    Plan {
+        #[arg(long)]
+        diff: bool,
+    },
+    Explain {
+        path: PathBuf,
+    },
+    Simulate {
+        #[arg(long)]
+        add: Vec<PathBuf>,
+        #[arg(long)]
+        disable: Vec<String>,
+    },
+    Rw {
+        #[command(subcommand)]
+        action: RwAction,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    #[arg(long, value_enum)]
+    pub fail_on: Option<Severity>,
+    #[arg(long, value_enum)]
+    pub min_level: Option<Severity>,
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    pub format: ReportFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Table,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RwAction {
+    Enable {
+        partition: String,
+    },
+    Disable {
+        partition: String,
+    },
+    Reset {
+        partition: String,
+    },
+    Usage,
+    Changes {
+        partition: String,
+    },
+    Export {
+        #[arg(long = "as")]
+        id: String,
+        #[arg(long)]
+        partition: Option<String>,
+        #[arg(long)]
+        reset: bool,
+    },
 }
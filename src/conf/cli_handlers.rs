@@ -1,22 +1,35 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use serde::Serialize;
 
 use crate::{
     conf::{
-        cli::Cli,
+        cli::{Cli, ReportArgs, ReportFormat, RwAction, Severity},
         config::{self, Config},
     },
-    core::{inventory, inventory::model as modules, ops::planner},
-    defs, utils,
+    core::{
+        inventory,
+        inventory::{impact, lint, model as modules},
+        ops::{diff, explain, planner, simulate},
+        rw,
+        state::RuntimeState,
+        storage,
+    },
+    defs,
+    mount::overlayfs::overlayfs::max_direct_layers,
+    sys::{self, capabilities},
+    utils,
 };
 
 #[derive(Serialize)]
 struct DiagnosticIssueJson {
     level: String,
+    code: planner::DiagnosticCode,
     context: String,
     message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    modules: Vec<String>,
 }
 
 fn load_config(cli: &Cli) -> Result<Config> {
@@ -114,7 +127,90 @@ pub fn handle_modules(cli: &Cli) -> Result<()> {
     modules::print_list(&config).context("Failed to list modules")
 }
 
-pub fn handle_conflicts(cli: &Cli) -> Result<()> {
+const EXIT_WARNING: i32 = 2;
+const EXIT_CRITICAL: i32 = 3;
+
+fn severity_level(severity: Severity) -> planner::DiagnosticLevel {
+    match severity {
+        Severity::Warning => planner::DiagnosticLevel::Warning,
+        Severity::Critical => planner::DiagnosticLevel::Critical,
+    }
+}
+
+fn level_name(level: planner::DiagnosticLevel) -> &'static str {
+    match level {
+        planner::DiagnosticLevel::Warning => "Warning",
+        planner::DiagnosticLevel::Critical => "Critical",
+    }
+}
+
+fn conflict_level(conflict: &planner::ConflictEntry) -> Option<planner::DiagnosticLevel> {
+    if conflict.policy.is_some() {
+        return None;
+    }
+
+    match conflict.kind {
+        planner::ConflictKind::Identical => None,
+        planner::ConflictKind::Divergent => Some(planner::DiagnosticLevel::Warning),
+        planner::ConflictKind::Type => Some(planner::DiagnosticLevel::Critical),
+    }
+}
+
+fn meets_min_level(level: Option<planner::DiagnosticLevel>, report: &ReportArgs) -> bool {
+    report
+        .min_level
+        .is_none_or(|min| level.is_some_and(|l| l >= severity_level(min)))
+}
+
+fn exit_on_threshold<I>(levels: I, fail_on: Option<Severity>)
+where
+    I: IntoIterator<Item = Option<planner::DiagnosticLevel>>,
+{
+    let Some(threshold) = fail_on.map(severity_level) else {
+        return;
+    };
+
+    if let Some(worst) = levels.into_iter().flatten().max()
+        && worst >= threshold
+    {
+        std::process::exit(match worst {
+            planner::DiagnosticLevel::Warning => EXIT_WARNING,
+            planner::DiagnosticLevel::Critical => EXIT_CRITICAL,
+        });
+    }
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        let last = cells.len().saturating_sub(1);
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == last {
+                    cell.to_string()
+                } else {
+                    format!("{:<width$}", cell, width = widths[i])
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(|c| c.as_str()).collect()));
+    }
+}
+
+pub fn handle_conflicts(cli: &Cli, module: Option<&str>, report_args: &ReportArgs) -> Result<()> {
     let config = load_config(cli)?;
 
     let module_list = inventory::scan(&config.moduledir, &config)
@@ -125,42 +221,451 @@ pub fn handle_conflicts(cli: &Cli) -> Result<()> {
 
     let report = plan.analyze();
 
-    let json =
-        serde_json::to_string(&report.conflicts).context("Failed to serialize conflict report")?;
+    let conflicts: Vec<planner::ConflictEntry> = report
+        .conflicts
+        .into_iter()
+        .filter(|c| module.is_none_or(|m| c.contending_modules.iter().any(|id| id == m)))
+        .filter(|c| meets_min_level(conflict_level(c), report_args))
+        .collect();
+
+    match report_args.format {
+        ReportFormat::Json => {
+            let json =
+                serde_json::to_string(&conflicts).context("Failed to serialize conflict report")?;
+            println!("{}", json);
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = conflicts
+                .iter()
+                .map(|c| {
+                    let kind = match c.kind {
+                        planner::ConflictKind::Identical => "identical",
+                        planner::ConflictKind::Divergent => "divergent",
+                        planner::ConflictKind::Type => "type",
+                    };
+                    let winner = match &c.policy {
+                        Some(_) => format!("{} (policy)", c.winner),
+                        None => c.winner.clone(),
+                    };
+                    vec![
+                        c.relative_path.clone(),
+                        kind.to_string(),
+                        winner,
+                        c.contending_modules.join(","),
+                    ]
+                })
+                .collect();
+            print_table(&["PATH", "KIND", "WINNER", "MODULES"], &rows);
+        }
+    }
+
+    exit_on_threshold(conflicts.iter().map(conflict_level), report_args.fail_on);
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct CapabilityMatrix {
+    storage: StorageCapabilities,
+    overlay: OverlayCapabilities,
+    mount_api: MountApiCapabilities,
+    umount: UmountCapabilities,
+    selected: SelectedModes,
+    filesystems: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StorageCapabilities {
+    tmpfs: bool,
+    ext4: bool,
+    erofs: bool,
+}
+
+#[derive(Serialize)]
+struct OverlayCapabilities {
+    listed: bool,
+    trial_mount: Option<bool>,
+    tmpfs_xattr: Option<bool>,
+    lowerdir_append: bool,
+    max_layers: usize,
+}
+
+#[derive(Serialize)]
+struct MountApiCapabilities {
+    fsopen: bool,
+    open_tree: bool,
+}
+
+#[derive(Serialize)]
+struct UmountCapabilities {
+    ksu_try_umount: bool,
+    disabled_by_config: bool,
+}
+
+#[derive(Serialize)]
+struct SelectedModes {
+    storage: String,
+    mount: String,
+}
+
+pub fn handle_capabilities(cli: &Cli) -> Result<()> {
+    let config = load_config(cli)?;
+
+    utils::check_ksu();
+    let caps = capabilities::get();
+
+    let storage_mode = storage::predict_mode(
+        matches!(config.overlay_mode, config::OverlayMode::Ext4),
+        matches!(config.overlay_mode, config::OverlayMode::Erofs),
+        caps,
+    );
+
+    let matrix = CapabilityMatrix {
+        storage: StorageCapabilities {
+            tmpfs: caps.tmpfs_xattr_supported(),
+            ext4: caps.loop_control && caps.filesystems.iter().any(|fs| fs == "ext4"),
+            erofs: caps.erofs,
+        },
+        overlay: OverlayCapabilities {
+            listed: caps.overlay_listed,
+            trial_mount: caps.overlay_mount,
+            tmpfs_xattr: caps.tmpfs_xattr,
+            lowerdir_append: caps.lowerdir_append,
+            max_layers: max_direct_layers(),
+        },
+        mount_api: MountApiCapabilities {
+            fsopen: caps.fsopen,
+            open_tree: caps.open_tree,
+        },
+        umount: UmountCapabilities {
+            ksu_try_umount: utils::KSU.load(std::sync::atomic::Ordering::Relaxed),
+            disabled_by_config: config.disable_umount,
+        },
+        selected: SelectedModes {
+            storage: storage_mode.to_string(),
+            mount: if caps.overlay_supported() {
+                "overlay"
+            } else {
+                "magic"
+            }
+            .to_string(),
+        },
+        filesystems: caps.filesystems.clone(),
+    };
+
+    let json = serde_json::to_string(&matrix).context("Failed to serialize capability matrix")?;
 
     println!("{}", json);
 
     Ok(())
 }
 
-pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
+pub fn handle_diagnostics(cli: &Cli, module: Option<&str>, report_args: &ReportArgs) -> Result<()> {
     let config = load_config(cli)?;
 
-    let module_list = inventory::scan(&config.moduledir, &config)
+    let inventory = inventory::scan_inventory(&config.moduledir, &config)
         .context("Failed to scan modules for diagnostics")?;
 
-    let plan = planner::generate(&config, &module_list, &config.moduledir)
+    let plan = planner::generate(&config, &inventory.modules, &config.moduledir)
         .context("Failed to generate plan for diagnostics")?;
 
-    let report = plan.analyze();
-
-    let json_issues: Vec<DiagnosticIssueJson> = report
+    let mut report = plan.analyze();
+
+    report.diagnostics.extend(
+        inventory
+            .modules
+            .iter()
+            .filter(|m| m.prop.id_mismatch(&m.id))
+            .map(|m| planner::DiagnosticIssue {
+                level: planner::DiagnosticLevel::Warning,
+                code: planner::DiagnosticCode::IdMismatch,
+                context: m.id.clone(),
+                message: format!(
+                    "Directory name does not match module.prop id '{}'",
+                    m.prop.id
+                ),
+                modules: Vec::new(),
+            }),
+    );
+
+    report.diagnostics.extend(
+        inventory
+            .skipped
+            .into_iter()
+            .map(|s| planner::DiagnosticIssue {
+                level: planner::DiagnosticLevel::Warning,
+                code: planner::DiagnosticCode::ModuleSkipped,
+                context: s.module.id,
+                message: format!("Module skipped: {}", s.reason),
+                modules: Vec::new(),
+            }),
+    );
+
+    let issues: Vec<planner::DiagnosticIssue> = report
         .diagnostics
         .into_iter()
-        .map(|i| DiagnosticIssueJson {
-            level: match i.level {
-                planner::DiagnosticLevel::Warning => "Warning".to_string(),
-                planner::DiagnosticLevel::Critical => "Critical".to_string(),
-            },
-            context: i.context,
-            message: i.message,
-        })
+        .filter(|i| module.is_none_or(|m| i.context == m || i.modules.iter().any(|id| id == m)))
+        .filter(|i| meets_min_level(Some(i.level), report_args))
+        .collect();
+    let levels: Vec<Option<planner::DiagnosticLevel>> =
+        issues.iter().map(|i| Some(i.level)).collect();
+
+    match report_args.format {
+        ReportFormat::Json => {
+            let json_issues: Vec<DiagnosticIssueJson> = issues
+                .into_iter()
+                .map(|i| DiagnosticIssueJson {
+                    level: level_name(i.level).to_string(),
+                    code: i.code,
+                    context: i.context,
+                    message: i.message,
+                    modules: i.modules,
+                })
+                .collect();
+
+            let json = serde_json::to_string(&json_issues)
+                .context("Failed to serialize diagnostics report")?;
+            println!("{}", json);
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = issues
+                .into_iter()
+                .map(|i| {
+                    vec![
+                        level_name(i.level).to_string(),
+                        i.code.as_str().to_string(),
+                        i.context,
+                        i.message,
+                    ]
+                })
+                .collect();
+            print_table(&["LEVEL", "CODE", "CONTEXT", "MESSAGE"], &rows);
+        }
+    }
+
+    exit_on_threshold(levels, report_args.fail_on);
+
+    Ok(())
+}
+
+pub fn handle_plan(cli: &Cli, show_diff: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_list =
+        inventory::scan(&config.moduledir, &config).context("Failed to scan modules for plan")?;
+
+    let plan = planner::generate(&config, &module_list, &config.moduledir)
+        .context("Failed to generate plan")?;
+
+    let json = if show_diff {
+        let state = RuntimeState::load().context("Failed to load runtime state")?;
+        if state.timestamp == 0 {
+            bail!("No runtime state recorded at boot; nothing to diff against");
+        }
+        serde_json::to_string(&diff::diff(&plan, &state))
+    } else {
+        serde_json::to_string(&plan)
+    }
+    .context("Failed to serialize plan")?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+pub fn handle_explain(cli: &Cli, path: &Path) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for explain")?;
+
+    let plan = planner::generate(&config, &module_list, &config.moduledir)
+        .context("Failed to generate plan for explain")?;
+
+    let state = RuntimeState::load().ok();
+    let explanation = explain::explain(&plan, path, state.as_ref());
+
+    let json = serde_json::to_string(&explanation).context("Failed to serialize explanation")?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+pub fn handle_simulate(cli: &Cli, add: &[PathBuf], disable: &[String]) -> Result<()> {
+    let config = load_config(cli)?;
+
+    if add.is_empty() && disable.is_empty() {
+        bail!("Nothing to simulate; pass --add <dir> or --disable <id>");
+    }
+
+    let simulation = simulate::simulate(&config, add, disable)?;
+
+    let json = serde_json::to_string(&simulation).context("Failed to serialize simulation")?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+pub fn handle_lint(cli: &Cli, target: &str, report_args: &ReportArgs) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_dir = if Path::new(target).is_dir() {
+        PathBuf::from(target)
+    } else {
+        utils::validate_module_id(target)?;
+        config.moduledir.join(target)
+    };
+
+    let issues: Vec<lint::LintIssue> = lint::lint_module(&module_dir, &config)
+        .with_context(|| format!("Failed to lint {}", module_dir.display()))?
+        .into_iter()
+        .filter(|i| meets_min_level(Some(i.level), report_args))
+        .collect();
+
+    match report_args.format {
+        ReportFormat::Json => {
+            let json = serde_json::to_string(&issues).context("Failed to serialize lint report")?;
+            println!("{}", json);
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = issues
+                .iter()
+                .map(|i| {
+                    vec![
+                        level_name(i.level).to_string(),
+                        i.code.as_str().to_string(),
+                        i.path.clone(),
+                        i.message.clone(),
+                    ]
+                })
+                .collect();
+            print_table(&["LEVEL", "CODE", "PATH", "MESSAGE"], &rows);
+        }
+    }
+
+    exit_on_threshold(issues.iter().map(|i| Some(i.level)), report_args.fail_on);
+
+    Ok(())
+}
+
+pub fn handle_impact(cli: &Cli, target: Option<&str>, summary: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_dirs: Vec<PathBuf> = match target {
+        Some(target) if Path::new(target).is_dir() => vec![PathBuf::from(target)],
+        Some(target) => {
+            utils::validate_module_id(target)?;
+            vec![config.moduledir.join(target)]
+        }
+        None => inventory::scan(&config.moduledir, &config)
+            .context("Failed to scan modules for impact report")?
+            .into_iter()
+            .map(|m| m.source_path)
+            .collect(),
+    };
+
+    let state = RuntimeState::load().unwrap_or_default();
+    let mut mounted_ids: Vec<&String> = state
+        .overlay_modules
+        .iter()
+        .chain(&state.magic_modules)
         .collect();
+    if !mounted_ids.is_empty() {
+        match sys::mount::isolate_stock_view(&config.mountsource, &config.partitions) {
+            Ok(()) => mounted_ids.clear(),
+            Err(e) => log::warn!("Comparing against live paths with modules mounted: {:#}", e),
+        }
+    }
+
+    let mut reports = Vec::new();
+    for module_dir in module_dirs {
+        let mounted = module_dir
+            .file_name()
+            .is_some_and(|id| mounted_ids.iter().any(|m| id == m.as_str()));
+        let mut report = impact::module_impact(&module_dir, &config, mounted)
+            .with_context(|| format!("Failed to analyze {}", module_dir.display()))?;
+        if summary {
+            report.entries.clear();
+        }
+        reports.push(report);
+    }
 
-    let json =
-        serde_json::to_string(&json_issues).context("Failed to serialize diagnostics report")?;
+    let json = serde_json::to_string(&reports).context("Failed to serialize impact report")?;
 
     println!("{}", json);
 
     Ok(())
 }
+
+pub fn handle_rw(cli: &Cli, action: &RwAction) -> Result<()> {
+    let config_path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(defs::CONFIG_FILE));
+
+    match action {
+        RwAction::Enable { partition } | RwAction::Disable { partition } => {
+            let mut config = load_config(cli)?;
+            let enabled = matches!(action, RwAction::Enable { .. });
+            if enabled {
+                rw::enable(&mut config, partition)?;
+            } else {
+                rw::disable(&mut config, partition)?;
+            }
+            config
+                .save_to_file(&config_path)
+                .context("Failed to update config file")?;
+            println!(
+                "Read-write overlay for {} will be {} on next boot.",
+                partition,
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+        RwAction::Reset { partition } => {
+            rw::reset(partition)?;
+            println!("Upperdir for {} has been reset.", partition);
+        }
+        RwAction::Usage => {
+            let config = load_config(cli)?;
+            let json = serde_json::to_string(&rw::usage(&config))
+                .context("Failed to serialize rw usage")?;
+            println!("{}", json);
+        }
+        RwAction::Export {
+            id,
+            partition,
+            reset,
+        } => {
+            let config = load_config(cli)?;
+            let (module_dir, partitions) = rw::export(&config, id, partition.as_deref())?;
+            println!("Exported read-write changes to {}", module_dir.display());
+
+            for partition in &partitions {
+                if !reset {
+                    println!(
+                        "Upperdir for {} still holds these changes and applies them on top of the module; run `hybrid-mount rw reset {}` or export with --reset.",
+                        partition, partition
+                    );
+                } else if rw::reset_or_schedule(partition)
+                    .with_context(|| format!("Failed to reset upperdir for {}", partition))?
+                {
+                    println!(
+                        "Upperdir for {} is in use and will be reset on next boot.",
+                        partition
+                    );
+                } else {
+                    println!("Upperdir for {} has been reset.", partition);
+                }
+            }
+        }
+        RwAction::Changes { partition } => {
+            let json = serde_json::to_string(&rw::changes(partition)?)
+                .context("Failed to serialize rw changes")?;
+            println!("{}", json);
+        }
+    }
+
+    Ok(())
+}
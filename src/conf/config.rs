@@ -24,6 +24,7 @@ pub enum DefaultMode {
     #[default]
     Overlay,
     Magic,
+    Auto,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
@@ -33,6 +34,18 @@ pub enum MountMode {
     Overlay,
     Magic,
     Ignore,
+    Auto,
+}
+
+impl MountMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MountMode::Overlay => "overlay",
+            MountMode::Magic => "magic",
+            MountMode::Ignore => "ignore",
+            MountMode::Auto => "auto",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -41,14 +54,220 @@ pub struct ModuleRules {
     pub default_mode: MountMode,
     #[serde(default)]
     pub paths: HashMap<String, MountMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
 }
 
 impl ModuleRules {
     pub fn get_mode(&self, relative_path: &str) -> MountMode {
-        if let Some(mode) = self.paths.get(relative_path) {
-            return mode.clone();
+        self.matching_rule(relative_path)
+            .map(|(_, mode)| mode.clone())
+            .unwrap_or_else(|| self.default_mode.clone())
+    }
+
+    pub fn matching_rule(&self, relative_path: &str) -> Option<(&str, &MountMode)> {
+        let relative_path = relative_path.trim_matches('/');
+        if let Some((pattern, mode)) = self.paths.get_key_value(relative_path) {
+            return Some((pattern.as_str(), mode));
         }
-        self.default_mode.clone()
+
+        self.paths
+            .iter()
+            .filter(|(pattern, _)| path_rule_matches(pattern, relative_path))
+            .max_by(|(a, _), (b, _)| {
+                rule_specificity(a)
+                    .cmp(&rule_specificity(b))
+                    .then_with(|| b.cmp(a))
+            })
+            .map(|(pattern, mode)| (pattern.as_str(), mode))
+    }
+
+    pub fn has_overrides_below(&self, relative_path: &str) -> bool {
+        let mode = self.get_mode(relative_path);
+        self.paths
+            .iter()
+            .any(|(pattern, m)| *m != mode && path_rule_reaches_below(pattern, relative_path))
+    }
+
+    pub fn get_priority(&self) -> i32 {
+        self.priority.unwrap_or(0)
+    }
+}
+
+pub fn is_glob_rule(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+pub fn path_rule_matches(pattern: &str, relative_path: &str) -> bool {
+    let pattern = split_rule_path(pattern);
+    let path = split_rule_path(relative_path);
+
+    match_segments(&pattern, &path)
+}
+
+pub fn conflict_policy_winner<'a>(
+    policy: &'a HashMap<String, String>,
+    relative_path: &str,
+) -> Option<(&'a str, &'a str)> {
+    policy
+        .iter()
+        .filter(|(pattern, _)| path_rule_matches(pattern, relative_path))
+        .max_by(|(a, _), (b, _)| {
+            rule_specificity(a)
+                .cmp(&rule_specificity(b))
+                .then_with(|| b.cmp(a))
+        })
+        .map(|(pattern, winner)| (pattern.as_str(), winner.as_str()))
+}
+
+pub fn conflict_policy_reaches_below(
+    policy: &HashMap<String, String>,
+    relative_path: &str,
+) -> bool {
+    policy
+        .keys()
+        .any(|pattern| path_rule_reaches_below(pattern, relative_path))
+}
+
+fn path_rule_reaches_below(pattern: &str, relative_path: &str) -> bool {
+    let pattern = split_rule_path(pattern);
+    let path = split_rule_path(relative_path);
+
+    reaches_below(&pattern, &path)
+}
+
+fn reaches_below(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, _) => false,
+        (Some(_), None) => !pattern.iter().all(|s| *s == "**"),
+        (Some((&"**", _)), Some(_)) => true,
+        (Some((segment, rest)), Some((name, path_rest))) => {
+            wildcard_match(segment.as_bytes(), name.as_bytes()) && reaches_below(rest, path_rest)
+        }
+    }
+}
+
+fn split_rule_path(path: &str) -> Vec<&str> {
+    path.trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => true,
+        Some((&"**", rest)) => (0..=path.len()).any(|i| match_segments(rest, &path[i..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                wildcard_match(segment.as_bytes(), name.as_bytes())
+                    && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn rule_specificity(pattern: &str) -> (usize, usize, usize) {
+    let segments = split_rule_path(pattern);
+
+    let literal_segments = segments.iter().filter(|s| !is_glob_rule(s)).count();
+    let bounded_segments = segments.iter().filter(|s| **s != "**").count();
+    let literal_chars = pattern.chars().filter(|c| !matches!(c, '*' | '?')).count();
+
+    (literal_segments, bounded_segments, literal_chars)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct OverlayOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metacopy: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xino: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userxattr: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volatile: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nosuid: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nodev: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noexec: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noatime: Option<bool>,
+}
+
+impl OverlayOptions {
+    pub fn merged_with(&self, other: &OverlayOptions) -> OverlayOptions {
+        OverlayOptions {
+            redirect_dir: other.redirect_dir.clone().or(self.redirect_dir.clone()),
+            metacopy: other.metacopy.or(self.metacopy),
+            index: other.index.or(self.index),
+            xino: other.xino.clone().or(self.xino.clone()),
+            userxattr: other.userxattr.or(self.userxattr),
+            volatile: other.volatile.or(self.volatile),
+            nosuid: other.nosuid.or(self.nosuid),
+            nodev: other.nodev.or(self.nodev),
+            noexec: other.noexec.or(self.noexec),
+            noatime: other.noatime.or(self.noatime),
+        }
+    }
+
+    pub fn fs_params(&self) -> Vec<(&'static str, Option<String>)> {
+        let on_off = |v: bool| if v { "on" } else { "off" }.to_string();
+
+        let mut params = Vec::new();
+        if let Some(v) = &self.redirect_dir {
+            params.push(("redirect_dir", Some(v.clone())));
+        }
+        if let Some(v) = self.metacopy {
+            params.push(("metacopy", Some(on_off(v))));
+        }
+        if let Some(v) = self.index {
+            params.push(("index", Some(on_off(v))));
+        }
+        if let Some(v) = &self.xino {
+            params.push(("xino", Some(v.clone())));
+        }
+        if self.userxattr == Some(true) {
+            params.push(("userxattr", None));
+        }
+        if self.volatile == Some(true) {
+            params.push(("volatile", None));
+        }
+        params
     }
 }
 
@@ -70,6 +289,18 @@ pub struct Config {
     pub default_mode: DefaultMode,
     #[serde(default)]
     pub rules: HashMap<String, ModuleRules>,
+    #[serde(default)]
+    pub module_order: Vec<String>,
+    #[serde(default)]
+    pub overlay_options: OverlayOptions,
+    #[serde(default)]
+    pub overlay_target_options: HashMap<String, OverlayOptions>,
+    #[serde(default)]
+    pub rw_partitions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rw_max_mb: Option<u64>,
+    #[serde(default)]
+    pub conflict_policy: HashMap<String, String>,
 }
 
 fn default_moduledir() -> PathBuf {
@@ -112,6 +343,12 @@ impl Default for Config {
             allow_umount_coexistence: false,
             default_mode: DefaultMode::default(),
             rules: HashMap::new(),
+            module_order: Vec::new(),
+            overlay_options: OverlayOptions::default(),
+            overlay_target_options: HashMap::new(),
+            rw_partitions: Vec::new(),
+            rw_max_mb: None,
+            conflict_policy: HashMap::new(),
         }
     }
 }
@@ -141,6 +378,14 @@ impl Config {
         Ok(())
     }
 
+    pub fn overlay_options_for(&self, target: &str) -> OverlayOptions {
+        let target = target.trim_end_matches('/');
+        match self.overlay_target_options.get(target) {
+            Some(options) => self.overlay_options.merged_with(options),
+            None => self.overlay_options.clone(),
+        }
+    }
+
     pub fn merge_with_cli(
         &mut self,
         moduledir: Option<PathBuf>,
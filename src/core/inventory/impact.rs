@@ -0,0 +1,208 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, Metadata},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::Path,
+};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{conf::config::Config, defs, mount::node::Node};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImpactKind {
+    Added,
+    AddedDir,
+    Replaced,
+    TypeChanged,
+    Deleted,
+    DirReplaced,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImpactEntry {
+    pub path: String,
+    pub kind: ImpactKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_delta: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden_entries: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImpactSummary {
+    pub added: u64,
+    pub added_dirs: u64,
+    pub replaced: u64,
+    pub type_changed: u64,
+    pub deleted: u64,
+    pub dirs_replaced: u64,
+    pub hidden_entries: u64,
+    pub size_delta: i64,
+}
+
+impl ImpactSummary {
+    fn record(&mut self, entry: &ImpactEntry) {
+        let counter = match entry.kind {
+            ImpactKind::Added => &mut self.added,
+            ImpactKind::AddedDir => &mut self.added_dirs,
+            ImpactKind::Replaced => &mut self.replaced,
+            ImpactKind::TypeChanged => &mut self.type_changed,
+            ImpactKind::Deleted => &mut self.deleted,
+            ImpactKind::DirReplaced => &mut self.dirs_replaced,
+        };
+        *counter += 1;
+        self.hidden_entries += entry.hidden_entries.unwrap_or(0);
+        self.size_delta += entry.size_delta.unwrap_or(0);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleImpact {
+    pub module_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unreliable_reason: Option<String>,
+    pub summary: ImpactSummary,
+    pub partitions: BTreeMap<String, ImpactSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<ImpactEntry>,
+}
+
+fn is_whiteout(metadata: &Metadata) -> bool {
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+fn content_size(metadata: &Metadata) -> i64 {
+    if metadata.is_dir() {
+        0
+    } else {
+        metadata.len() as i64
+    }
+}
+
+fn same_kind(a: &Metadata, b: &Metadata) -> bool {
+    (a.is_file() && b.is_file()) || (a.is_symlink() && b.is_symlink())
+}
+
+fn hidden_stock_entries(module_path: &Path, stock_path: &Path) -> u64 {
+    let provided: HashSet<_> = fs::read_dir(module_path)
+        .map(|entries| entries.flatten().map(|e| e.file_name()).collect())
+        .unwrap_or_default();
+
+    fs::read_dir(stock_path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| !provided.contains(&e.file_name()))
+                .count() as u64
+        })
+        .unwrap_or(0)
+}
+
+fn classify(module_path: &Path, module: &Metadata, stock_path: &Path) -> Option<ImpactEntry> {
+    let stock = fs::symlink_metadata(stock_path).ok();
+    let entry = |kind, size_delta, hidden_entries| {
+        Some(ImpactEntry {
+            path: stock_path.display().to_string(),
+            kind,
+            size_delta,
+            hidden_entries,
+        })
+    };
+
+    if is_whiteout(module) {
+        return stock
+            .and_then(|stock| entry(ImpactKind::Deleted, Some(-content_size(&stock)), None));
+    }
+
+    let Some(stock) = stock else {
+        return if module.is_dir() {
+            entry(ImpactKind::AddedDir, None, None)
+        } else {
+            entry(ImpactKind::Added, Some(content_size(module)), None)
+        };
+    };
+
+    let size_delta = Some(content_size(module) - content_size(&stock));
+
+    if module.is_dir() {
+        if !stock.is_dir() {
+            entry(ImpactKind::TypeChanged, size_delta, None)
+        } else if Node::dir_is_replace(module_path) {
+            let hidden = hidden_stock_entries(module_path, stock_path);
+            entry(ImpactKind::DirReplaced, None, Some(hidden))
+        } else {
+            None
+        }
+    } else if same_kind(module, &stock) {
+        entry(ImpactKind::Replaced, size_delta, None)
+    } else {
+        entry(ImpactKind::TypeChanged, size_delta, None)
+    }
+}
+
+pub fn module_impact(module_dir: &Path, config: &Config, mounted: bool) -> Result<ModuleImpact> {
+    if !module_dir.is_dir() {
+        bail!("Module directory not found: {}", module_dir.display());
+    }
+
+    let module_id = module_dir
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let is_partition = |name: &str| {
+        defs::BUILTIN_PARTITIONS.contains(&name) || config.partitions.iter().any(|p| p == name)
+    };
+
+    let mut impact = ModuleImpact {
+        module_id,
+        unreliable_reason: mounted.then(|| {
+            "module is mounted and the stock view could not be isolated, so live paths may already show its content".to_string()
+        }),
+        summary: ImpactSummary::default(),
+        partitions: BTreeMap::new(),
+        entries: Vec::new(),
+    };
+
+    let mut partitions: Vec<String> = fs::read_dir(module_dir)?
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| is_partition(name))
+        .collect();
+    partitions.sort();
+
+    for partition in partitions {
+        let summary = impact.partitions.entry(partition.clone()).or_default();
+
+        for entry in WalkDir::new(module_dir.join(&partition))
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+        {
+            if entry.file_name() == defs::REPLACE_DIR_FILE_NAME {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let relative = entry
+                .path()
+                .strip_prefix(module_dir)
+                .unwrap_or(entry.path());
+
+            if let Some(item) = classify(entry.path(), &metadata, &Path::new("/").join(relative)) {
+                summary.record(&item);
+                impact.summary.record(&item);
+                impact.entries.push(item);
+            }
+        }
+    }
+
+    Ok(impact)
+}
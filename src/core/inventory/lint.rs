@@ -0,0 +1,274 @@
+use std::{
+    fs,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::Path,
+};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use super::scanner;
+use crate::{
+    conf::config::{self, Config},
+    core::ops::planner::{DiagnosticCode, DiagnosticLevel},
+    defs, utils,
+};
+
+const AUXILIARY_DIRS: &[&str] = &[
+    "META-INF", "common", "webroot", "zygisk", "bin", "lib", "tools", "scripts", "config",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LintIssue {
+    pub level: DiagnosticLevel,
+    pub code: DiagnosticCode,
+    pub path: String,
+    pub message: String,
+}
+
+struct Linter<'a> {
+    root: &'a Path,
+    issues: Vec<LintIssue>,
+}
+
+impl Linter<'_> {
+    fn push(&mut self, level: DiagnosticLevel, code: DiagnosticCode, path: &Path, message: String) {
+        let rel = path.strip_prefix(self.root).unwrap_or(path);
+        self.issues.push(LintIssue {
+            level,
+            code,
+            path: rel.display().to_string(),
+            message,
+        });
+    }
+}
+
+pub fn lint_module(module_dir: &Path, config: &Config) -> Result<Vec<LintIssue>> {
+    if !module_dir.is_dir() {
+        bail!("Module directory not found: {}", module_dir.display());
+    }
+
+    let module_id = module_dir
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut linter = Linter {
+        root: module_dir,
+        issues: Vec::new(),
+    };
+
+    let is_partition = |name: &str| {
+        defs::BUILTIN_PARTITIONS.contains(&name) || config.partitions.iter().any(|p| p == name)
+    };
+
+    let check_selinux = utils::selinux_enabled();
+
+    for entry in fs::read_dir(module_dir)?.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if !path.is_dir() || name.starts_with('.') {
+            continue;
+        }
+
+        if !is_partition(&name) {
+            if !AUXILIARY_DIRS.contains(&name.as_str()) {
+                linter.push(
+                    DiagnosticLevel::Warning,
+                    DiagnosticCode::UnknownPartition,
+                    &path,
+                    format!(
+                        "'{}' is not a known partition and will not be mounted",
+                        name
+                    ),
+                );
+            }
+            continue;
+        }
+
+        let system_root = Path::new("/").join(&name);
+        if !system_root.exists() {
+            linter.push(
+                DiagnosticLevel::Warning,
+                DiagnosticCode::AbsentPartition,
+                &path,
+                format!(
+                    "Partition {} does not exist on this device",
+                    system_root.display()
+                ),
+            );
+        } else if let Ok(sub_entries) = fs::read_dir(&path) {
+            for sub in sub_entries.flatten() {
+                let target = system_root.join(sub.file_name());
+                if sub.path().is_dir() && !target.exists() {
+                    linter.push(
+                        DiagnosticLevel::Warning,
+                        DiagnosticCode::MissingTarget,
+                        &sub.path(),
+                        format!("Target directory {} does not exist", target.display()),
+                    );
+                }
+            }
+        }
+
+        lint_partition_tree(&mut linter, &path, check_selinux);
+    }
+
+    lint_rules(&mut linter, module_dir, &module_id, config);
+
+    Ok(linter.issues)
+}
+
+fn lint_partition_tree(linter: &mut Linter, partition_dir: &Path, check_selinux: bool) {
+    for entry in WalkDir::new(partition_dir).into_iter().flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        let file_type = metadata.file_type();
+
+        if file_type.is_symlink() {
+            if let Some(target) = utils::dead_absolute_symlink(path) {
+                linter.push(
+                    DiagnosticLevel::Warning,
+                    DiagnosticCode::DeadSymlink,
+                    path,
+                    format!("Dead absolute symlink -> {}", target.display()),
+                );
+            }
+        } else if file_type.is_char_device() && metadata.rdev() == 0 {
+            // overlay whiteout
+        } else if file_type.is_char_device()
+            || file_type.is_block_device()
+            || file_type.is_fifo()
+            || file_type.is_socket()
+        {
+            linter.push(
+                DiagnosticLevel::Critical,
+                DiagnosticCode::DeviceNode,
+                path,
+                "Special file that is not a whiteout".to_string(),
+            );
+        }
+
+        if let Some(mode) = utils::setid_mode(&metadata) {
+            linter.push(
+                DiagnosticLevel::Warning,
+                DiagnosticCode::SetidBit,
+                path,
+                format!("setuid/setgid bit set (mode {:o})", mode),
+            );
+        }
+
+        if check_selinux && utils::lacks_selinux_label(path) {
+            linter.push(
+                DiagnosticLevel::Warning,
+                DiagnosticCode::MissingSelinuxLabel,
+                path,
+                "No SELinux label".to_string(),
+            );
+        }
+    }
+}
+
+fn lint_rules(linter: &mut Linter, module_dir: &Path, module_id: &str, config: &Config) {
+    let rules_path = module_dir.join("hybrid_rules.json");
+    let mut path_rules: Vec<String> = Vec::new();
+
+    if rules_path.exists() {
+        match fs::read_to_string(&rules_path) {
+            Ok(content) => match serde_json::from_str::<serde_json::Value>(&content) {
+                Ok(serde_json::Value::Object(map)) => {
+                    for key in map.keys() {
+                        if !scanner::RULE_KEYS.contains(&key.as_str()) {
+                            linter.push(
+                                DiagnosticLevel::Warning,
+                                DiagnosticCode::UnknownRuleKey,
+                                &rules_path,
+                                format!("Unknown key '{}'", key),
+                            );
+                        }
+                    }
+
+                    if let Err(e) = scanner::validate_rules(&content) {
+                        linter.push(
+                            DiagnosticLevel::Critical,
+                            DiagnosticCode::InvalidRules,
+                            &rules_path,
+                            format!("Invalid rule value: {}", e),
+                        );
+                    }
+
+                    if let Some(serde_json::Value::Object(paths)) = map.get("paths") {
+                        path_rules.extend(paths.keys().cloned());
+                    }
+                }
+                Ok(_) => linter.push(
+                    DiagnosticLevel::Critical,
+                    DiagnosticCode::InvalidRules,
+                    &rules_path,
+                    "Rules must be a JSON object".to_string(),
+                ),
+                Err(e) => linter.push(
+                    DiagnosticLevel::Critical,
+                    DiagnosticCode::InvalidRules,
+                    &rules_path,
+                    format!("Invalid JSON: {}", e),
+                ),
+            },
+            Err(e) => linter.push(
+                DiagnosticLevel::Critical,
+                DiagnosticCode::InvalidRules,
+                &rules_path,
+                format!("Unreadable: {}", e),
+            ),
+        }
+    }
+
+    if let Some(global_rules) = config.rules.get(module_id) {
+        path_rules.extend(global_rules.paths.keys().cloned());
+    }
+
+    path_rules.sort();
+    path_rules.dedup();
+
+    let module_paths: Vec<String> = if path_rules.iter().any(|r| config::is_glob_rule(r)) {
+        WalkDir::new(module_dir)
+            .min_depth(1)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| {
+                e.path()
+                    .strip_prefix(module_dir)
+                    .ok()
+                    .map(|p| p.to_string_lossy().to_string())
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    for rule in path_rules {
+        let matched = if config::is_glob_rule(&rule) {
+            module_paths
+                .iter()
+                .any(|p| config::path_rule_matches(&rule, p))
+        } else {
+            module_dir
+                .join(rule.trim_matches('/'))
+                .symlink_metadata()
+                .is_ok()
+        };
+
+        if !matched {
+            linter.push(
+                DiagnosticLevel::Warning,
+                DiagnosticCode::DanglingPathRule,
+                &rules_path,
+                format!("Path rule '{}' matches nothing in the module", rule),
+            );
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod impact;
+pub mod lint;
 pub mod model;
 pub mod scanner;
 
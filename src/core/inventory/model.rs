@@ -1,57 +1,90 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fs::{self},
     io::{BufRead, BufReader},
     path::Path,
     sync::OnceLock,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use regex_lite::Regex;
 use serde::Serialize;
 
 use super::scanner as inventory;
-use crate::{
-    conf::config::{self, MountMode},
-    core::state::RuntimeState,
-    defs, utils,
-};
+use crate::{conf::config, core::state::RuntimeState, defs, utils};
 
 static MODULE_PROP_REGEX: OnceLock<Regex> = OnceLock::new();
 
-#[derive(Default)]
-struct ModuleProp {
-    name: String,
-    version: String,
-    author: String,
-    description: String,
+#[derive(Debug, Clone, Default)]
+pub struct ModuleProp {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub version_code: Option<i64>,
+    pub author: String,
+    pub description: String,
+    pub update_json: Option<String>,
+    pub requires: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub extra: BTreeMap<String, String>,
 }
 
-impl From<&Path> for ModuleProp {
-    fn from(path: &Path) -> Self {
+fn split_id_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+impl ModuleProp {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        Ok(Self::parse(&content))
+    }
+
+    pub fn parse(content: &str) -> Self {
         let mut prop = ModuleProp::default();
         let re = MODULE_PROP_REGEX.get_or_init(|| {
             Regex::new(r"^([a-zA-Z0-9_.]+)=(.*)$").expect("Failed to compile module prop regex")
         });
 
-        if let Ok(file) = fs::File::open(path) {
-            for line in BufReader::new(file).lines().map_while(Result::ok) {
-                if let Some(caps) = re.captures(line.trim()) {
-                    let k = caps.get(1).map_or("", |m| m.as_str());
-                    let v = caps.get(2).map_or("", |m| m.as_str());
-
-                    match k {
-                        "name" => prop.name = v.to_string(),
-                        "version" => prop.version = v.to_string(),
-                        "author" => prop.author = v.to_string(),
-                        "description" => prop.description = v.to_string(),
-                        _ => {}
+        for line in content.lines() {
+            if let Some(caps) = re.captures(line.trim()) {
+                let k = caps.get(1).map_or("", |m| m.as_str());
+                let v = caps.get(2).map_or("", |m| m.as_str());
+
+                match k {
+                    "id" => prop.id = v.trim().to_string(),
+                    "name" => prop.name = v.to_string(),
+                    "version" => prop.version = v.to_string(),
+                    "versionCode" => prop.version_code = v.trim().parse().ok(),
+                    "author" => prop.author = v.to_string(),
+                    "description" => prop.description = v.to_string(),
+                    "updateJson" => prop.update_json = Some(v.trim().to_string()),
+                    "requires" => prop.requires = split_id_list(v),
+                    "conflicts" => prop.conflicts = split_id_list(v),
+                    _ => {
+                        prop.extra.insert(k.to_string(), v.to_string());
                     }
                 }
             }
         }
+
         prop
     }
+
+    pub fn id_mismatch(&self, dir_name: &str) -> bool {
+        !self.id.is_empty() && self.id != dir_name
+    }
+}
+
+impl From<&Path> for ModuleProp {
+    fn from(path: &Path) -> Self {
+        Self::load(path).unwrap_or_default()
+    }
 }
 
 #[derive(Serialize)]
@@ -59,38 +92,58 @@ struct ModuleInfo {
     id: String,
     name: String,
     version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_code: Option<i64>,
     author: String,
     description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update_json: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prop_id: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    extra: BTreeMap<String, String>,
     mode: String,
     is_mounted: bool,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
     rules: config::ModuleRules,
 }
 
 impl ModuleInfo {
-    fn new(m: inventory::Module, mounted_set: &HashSet<&str>) -> Self {
-        let prop = ModuleProp::from(m.source_path.join("module.prop").as_path());
-
-        let mode_str = match m.rules.default_mode {
-            MountMode::Overlay => "auto",
-            MountMode::Magic => "magic",
-            MountMode::Ignore => "ignore",
-        };
+    fn new(m: inventory::Module, mounted_set: &HashSet<&str>, reason: Option<String>) -> Self {
+        let prop = m.prop;
 
         Self {
             is_mounted: mounted_set.contains(m.id.as_str()),
+            prop_id: prop.id_mismatch(&m.id).then_some(prop.id),
             id: m.id,
             name: prop.name,
             version: prop.version,
+            version_code: prop.version_code,
             author: prop.author,
             description: prop.description,
-            mode: mode_str.to_string(),
+            update_json: prop.update_json,
+            extra: prop.extra,
+            mode: match m.rules.default_mode {
+                config::MountMode::Overlay | config::MountMode::Auto => "auto",
+                ref mode => mode.as_str(),
+            }
+            .to_string(),
+            status: if reason.is_some() {
+                "skipped"
+            } else {
+                "active"
+            }
+            .to_string(),
+            reason,
             rules: m.rules,
         }
     }
 }
 
 pub fn print_list(config: &config::Config) -> Result<()> {
-    let modules = inventory::scan(&config.moduledir, config)?;
+    let inventory = inventory::scan_inventory(&config.moduledir, config)?;
 
     let state = RuntimeState::load().unwrap_or_default();
 
@@ -101,9 +154,16 @@ pub fn print_list(config: &config::Config) -> Result<()> {
         .map(|s| s.as_str())
         .collect();
 
-    let infos: Vec<ModuleInfo> = modules
+    let infos: Vec<ModuleInfo> = inventory
+        .modules
         .into_iter()
-        .map(|m| ModuleInfo::new(m, &mounted_ids))
+        .map(|m| ModuleInfo::new(m, &mounted_ids, None))
+        .chain(
+            inventory
+                .skipped
+                .into_iter()
+                .map(|s| ModuleInfo::new(s.module, &mounted_ids, Some(s.reason))),
+        )
         .collect();
 
     println!("{}", serde_json::to_string(&infos)?);
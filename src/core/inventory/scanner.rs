@@ -1,33 +1,61 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
 };
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use rayon::prelude::*;
 use serde::Deserialize;
 
+use super::model::ModuleProp;
 use crate::{
     conf::config::{self, ModuleRules, MountMode},
-    defs,
+    defs, utils,
 };
 
+pub const RULE_KEYS: &[&str] = &["default_mode", "paths", "priority", "requires", "conflicts"];
+
 #[derive(Deserialize)]
 struct PartialRules {
     default_mode: Option<MountMode>,
     paths: Option<HashMap<String, MountMode>>,
+    priority: Option<i32>,
+    requires: Option<Vec<String>>,
+    conflicts: Option<Vec<String>>,
+}
+
+pub fn validate_rules(content: &str) -> Result<()> {
+    serde_json::from_str::<PartialRules>(content)?;
+    Ok(())
+}
+
+fn merge_ids(target: &mut Vec<String>, ids: impl IntoIterator<Item = String>) {
+    for id in ids {
+        if !target.contains(&id) {
+            target.push(id);
+        }
+    }
 }
 
-fn load_module_rules(module_dir: &Path, module_id: &str, cfg: &config::Config) -> ModuleRules {
+fn load_module_rules(
+    module_dir: &Path,
+    module_id: &str,
+    prop: &ModuleProp,
+    cfg: &config::Config,
+) -> ModuleRules {
     let mut rules = ModuleRules {
         default_mode: match cfg.default_mode {
             config::DefaultMode::Overlay => MountMode::Overlay,
             config::DefaultMode::Magic => MountMode::Magic,
+            config::DefaultMode::Auto => MountMode::Auto,
         },
         ..Default::default()
     };
 
+    merge_ids(&mut rules.requires, prop.requires.clone());
+    merge_ids(&mut rules.conflicts, prop.conflicts.clone());
+
     let internal_config = module_dir.join("hybrid_rules.json");
 
     if internal_config.exists() {
@@ -40,6 +68,11 @@ fn load_module_rules(module_dir: &Path, module_id: &str, cfg: &config::Config) -
                     if let Some(paths) = partial.paths {
                         rules.paths = paths;
                     }
+                    if partial.priority.is_some() {
+                        rules.priority = partial.priority;
+                    }
+                    merge_ids(&mut rules.requires, partial.requires.unwrap_or_default());
+                    merge_ids(&mut rules.conflicts, partial.conflicts.unwrap_or_default());
                 }
                 Err(e) => {
                     log::warn!("Failed to parse rules for module '{}': {}", module_id, e)
@@ -52,6 +85,11 @@ fn load_module_rules(module_dir: &Path, module_id: &str, cfg: &config::Config) -
     if let Some(global_rules) = cfg.rules.get(module_id) {
         rules.default_mode = global_rules.default_mode.clone();
         rules.paths.extend(global_rules.paths.clone());
+        if global_rules.priority.is_some() {
+            rules.priority = global_rules.priority;
+        }
+        merge_ids(&mut rules.requires, global_rules.requires.clone());
+        merge_ids(&mut rules.conflicts, global_rules.conflicts.clone());
     }
 
     rules
@@ -61,52 +99,262 @@ fn load_module_rules(module_dir: &Path, module_id: &str, cfg: &config::Config) -
 pub struct Module {
     pub id: String,
     pub source_path: PathBuf,
+    pub prop: ModuleProp,
     pub rules: ModuleRules,
 }
 
+#[derive(Debug, Clone)]
+pub struct SkippedModule {
+    pub module: Module,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Inventory {
+    pub modules: Vec<Module>,
+    pub skipped: Vec<SkippedModule>,
+}
+
 pub fn scan(source_dir: &Path, cfg: &config::Config) -> Result<Vec<Module>> {
+    let inventory = scan_inventory(source_dir, cfg)?;
+
+    for skipped in &inventory.skipped {
+        log::warn!(
+            "Skipping module '{}': {}",
+            skipped.module.id,
+            skipped.reason
+        );
+    }
+
+    Ok(inventory.modules)
+}
+
+fn load_module(path: PathBuf, cfg: &config::Config, id_from_prop: bool) -> Option<(Module, bool)> {
+    if !path.is_dir() {
+        return None;
+    }
+
+    let dir_name = path.file_name()?.to_string_lossy().to_string();
+
+    if matches!(
+        dir_name.as_str(),
+        "hybrid-mount" | "lost+found" | ".git" | ".idea" | ".vscode"
+    ) {
+        return None;
+    }
+
+    if path.join(defs::DISABLE_FILE_NAME).exists() || path.join(defs::REMOVE_FILE_NAME).exists() {
+        return None;
+    }
+
+    let skip_mount = path.join(defs::SKIP_MOUNT_FILE_NAME).exists();
+
+    let prop = ModuleProp::from(path.join("module.prop").as_path());
+
+    let id = if id_from_prop && !prop.id.is_empty() {
+        prop.id.clone()
+    } else {
+        if prop.id_mismatch(&dir_name) {
+            log::warn!(
+                "Module directory '{}' does not match its module.prop id '{}'",
+                dir_name,
+                prop.id
+            );
+        }
+        dir_name
+    };
+
+    let rules = load_module_rules(&path, &id, &prop, cfg);
+
+    Some((
+        Module {
+            id,
+            source_path: path,
+            prop,
+            rules,
+        },
+        skip_mount,
+    ))
+}
+
+fn read_source_dir(source_dir: &Path, cfg: &config::Config) -> Result<Vec<(Module, bool)>> {
     if !source_dir.exists() {
         return Ok(Vec::new());
     }
 
     let dir_entries = fs::read_dir(source_dir)?.collect::<std::io::Result<Vec<_>>>()?;
 
-    let mut modules: Vec<Module> = dir_entries
+    Ok(dir_entries
         .into_par_iter()
-        .filter_map(|entry| {
-            let path = entry.path();
+        .filter_map(|entry| load_module(entry.path(), cfg, false))
+        .collect())
+}
 
-            if !path.is_dir() {
-                return None;
-            }
+fn build_inventory(
+    source_dir: &Path,
+    scanned: Vec<(Module, bool)>,
+    cfg: &config::Config,
+) -> Inventory {
+    let mut passive_ids = HashSet::new();
+    let mut modules = Vec::new();
+    for (module, skip_mount) in scanned {
+        if skip_mount {
+            passive_ids.insert(module.id);
+        } else {
+            modules.push(module);
+        }
+    }
 
-            let id = entry.file_name().to_string_lossy().to_string();
+    sort_by_precedence(&mut modules, &cfg.module_order);
 
-            if matches!(
-                id.as_str(),
-                "hybrid-mount" | "lost+found" | ".git" | ".idea" | ".vscode"
-            ) {
-                return None;
+    resolve_dependencies(source_dir, modules, &passive_ids)
+}
+
+pub fn scan_inventory(source_dir: &Path, cfg: &config::Config) -> Result<Inventory> {
+    let scanned = read_source_dir(source_dir, cfg)?;
+
+    Ok(build_inventory(source_dir, scanned, cfg))
+}
+
+pub fn scan_virtual(
+    source_dir: &Path,
+    cfg: &config::Config,
+    add: &[PathBuf],
+    disable: &[String],
+) -> Result<Inventory> {
+    for id in disable {
+        let path = source_dir.join(id);
+        if !path.is_dir() {
+            bail!("Module '{}' is not installed", id);
+        }
+        if path.join(defs::DISABLE_FILE_NAME).exists() || path.join(defs::REMOVE_FILE_NAME).exists()
+        {
+            bail!("Module '{}' is already disabled", id);
+        }
+    }
+
+    let mut scanned = read_source_dir(source_dir, cfg)?;
+    scanned.retain(|(module, _)| !disable.contains(&module.id));
+
+    let mut added_ids: Vec<String> = Vec::new();
+    for dir in add {
+        let Some((module, skip_mount)) = load_module(dir.clone(), cfg, true) else {
+            bail!("{} is not a loadable module directory", dir.display());
+        };
+        utils::validate_module_id(&module.id)?;
+
+        if added_ids.contains(&module.id) {
+            bail!("Module '{}' is added more than once", module.id);
+        }
+        added_ids.push(module.id.clone());
+
+        scanned.retain(|(m, _)| m.id != module.id);
+        scanned.push((module, skip_mount));
+    }
+
+    Ok(build_inventory(source_dir, scanned, cfg))
+}
+
+fn resolve_dependencies(
+    source_dir: &Path,
+    modules: Vec<Module>,
+    passive_ids: &HashSet<String>,
+) -> Inventory {
+    let mut reasons: Vec<Option<String>> = vec![None; modules.len()];
+
+    let missing_reason = |dep: &str| {
+        if source_dir.join(dep).is_dir() {
+            format!("required module '{}' is disabled", dep)
+        } else {
+            format!("required module '{}' is not installed", dep)
+        }
+    };
+
+    let resolve_requires = |reasons: &mut Vec<Option<String>>| loop {
+        let active: HashSet<&str> = modules
+            .iter()
+            .zip(reasons.iter())
+            .filter(|(_, r)| r.is_none())
+            .map(|(m, _)| m.id.as_str())
+            .collect();
+
+        let mut changed = false;
+        for (module, reason) in modules.iter().zip(reasons.iter_mut()) {
+            if reason.is_some() {
+                continue;
             }
 
-            if path.join(defs::DISABLE_FILE_NAME).exists()
-                || path.join(defs::REMOVE_FILE_NAME).exists()
-                || path.join(defs::SKIP_MOUNT_FILE_NAME).exists()
-            {
-                return None;
+            let unmet =
+                module.rules.requires.iter().find(|dep| {
+                    !active.contains(dep.as_str()) && !passive_ids.contains(dep.as_str())
+                });
+
+            if let Some(dep) = unmet {
+                *reason = Some(if modules.iter().any(|m| &m.id == dep) {
+                    format!("required module '{}' was skipped", dep)
+                } else {
+                    missing_reason(dep)
+                });
+                changed = true;
             }
+        }
+
+        if !changed {
+            break;
+        }
+    };
 
-            let rules = load_module_rules(&path, &id, cfg);
+    resolve_requires(&mut reasons);
 
-            Some(Module {
-                id,
-                source_path: path,
-                rules,
-            })
-        })
-        .collect();
+    for i in 0..modules.len() {
+        if reasons[i].is_some() {
+            continue;
+        }
 
-    modules.sort_by(|a, b| b.id.cmp(&a.id));
+        let current = &modules[i];
+        let winner = modules[..i]
+            .iter()
+            .zip(reasons.iter())
+            .filter(|(_, r)| r.is_none())
+            .map(|(m, _)| m)
+            .find(|higher| {
+                current.rules.conflicts.contains(&higher.id)
+                    || higher.rules.conflicts.contains(&current.id)
+            });
+
+        if let Some(higher) = winner {
+            reasons[i] = Some(format!(
+                "conflicts with higher-priority module '{}'",
+                higher.id
+            ));
+        }
+    }
+
+    resolve_requires(&mut reasons);
+
+    let mut inventory = Inventory::default();
+    for (module, reason) in modules.into_iter().zip(reasons) {
+        match reason {
+            Some(reason) => inventory.skipped.push(SkippedModule { module, reason }),
+            None => inventory.modules.push(module),
+        }
+    }
+
+    inventory
+}
+
+pub fn sort_by_precedence(modules: &mut [Module], module_order: &[String]) {
+    let order_index = |id: &str| {
+        module_order
+            .iter()
+            .position(|o| o == id)
+            .unwrap_or(usize::MAX)
+    };
 
-    Ok(modules)
+    modules.sort_by(|a, b| {
+        order_index(&a.id)
+            .cmp(&order_index(&b.id))
+            .then_with(|| b.rules.get_priority().cmp(&a.rules.get_priority()))
+            .then_with(|| b.id.cmp(&a.id))
+    });
 }
@@ -95,13 +95,15 @@ impl MountController<StorageReady> {
 
         sync::perform_sync(&modules, &self.state.handle.mount_point)?;
 
-        if self.state.handle.mode == "erofs_staging" {
+        if self.state.handle.mode == storage::EROFS_STAGING_MODE {
+            let may_use_magic = |mode: &inventory::MountMode| {
+                matches!(
+                    mode,
+                    inventory::MountMode::Magic | inventory::MountMode::Auto
+                )
+            };
             let needs_magic = modules.iter().any(|m| {
-                m.rules.default_mode == inventory::MountMode::Magic
-                    || m.rules
-                        .paths
-                        .values()
-                        .any(|v| *v == inventory::MountMode::Magic)
+                may_use_magic(&m.rules.default_mode) || m.rules.paths.values().any(may_use_magic)
             });
 
             if needs_magic {
@@ -170,16 +172,7 @@ impl MountController<Executed> {
             self.state.result.magic_module_ids.len(),
         );
 
-        let mut active_mounts: Vec<String> = self
-            .state
-            .plan
-            .overlay_ops
-            .iter()
-            .map(|op| op.partition_name.clone())
-            .collect();
-
-        active_mounts.sort();
-        active_mounts.dedup();
+        let active_mounts = self.state.plan.active_partitions();
 
         let state = state::RuntimeState::new(
             self.state.handle.mode,
@@ -187,6 +180,7 @@ impl MountController<Executed> {
             self.state.result.overlay_module_ids,
             self.state.result.magic_module_ids,
             active_mounts,
+            self.state.result.target_outcomes,
         );
 
         if let Err(e) = state.save() {
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::planner::MountPlan;
+use crate::{core::state::RuntimeState, sys::capabilities};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TargetSnapshot {
+    pub mechanism: String,
+    pub modules: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TargetChange {
+    pub target: String,
+    pub changes: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<TargetSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<TargetSnapshot>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ListChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanDiff {
+    pub booted_at: u64,
+    pub targets: Vec<TargetChange>,
+    pub overlay_modules: ListChange,
+    pub magic_modules: ListChange,
+    pub active_mounts: ListChange,
+}
+
+fn list_change(before: &[String], after: &[String]) -> ListChange {
+    ListChange {
+        added: after
+            .iter()
+            .filter(|id| !before.contains(id))
+            .cloned()
+            .collect(),
+        removed: before
+            .iter()
+            .filter(|id| !after.contains(id))
+            .cloned()
+            .collect(),
+    }
+}
+
+fn target_changes(before: &TargetSnapshot, after: &TargetSnapshot) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+
+    if before.mechanism != after.mechanism {
+        changes.push("mechanism");
+    }
+
+    let mut before_set = before.modules.clone();
+    let mut after_set = after.modules.clone();
+    before_set.sort();
+    after_set.sort();
+
+    if before_set != after_set {
+        changes.push("modules");
+    } else if before.modules != after.modules {
+        changes.push("layer_order");
+    }
+
+    changes
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanComparison {
+    pub targets: Vec<TargetChange>,
+    pub overlay_modules: ListChange,
+    pub magic_modules: ListChange,
+    pub active_mounts: ListChange,
+}
+
+fn overlay_mechanism() -> &'static str {
+    if capabilities::get().overlay_supported() {
+        "overlay"
+    } else {
+        "magic_fallback"
+    }
+}
+
+fn add_target(
+    targets: &mut BTreeMap<String, TargetSnapshot>,
+    target: &str,
+    mechanism: &str,
+    modules: &[String],
+) {
+    match targets.get_mut(target) {
+        Some(snapshot) => {
+            snapshot.mechanism = format!("{}+{}", snapshot.mechanism, mechanism);
+            snapshot.modules.extend(modules.iter().cloned());
+        }
+        None => {
+            targets.insert(
+                target.to_string(),
+                TargetSnapshot {
+                    mechanism: mechanism.to_string(),
+                    modules: modules.to_vec(),
+                },
+            );
+        }
+    }
+}
+
+fn plan_targets(plan: &MountPlan) -> BTreeMap<String, TargetSnapshot> {
+    let mechanism = overlay_mechanism();
+
+    let mut targets = BTreeMap::new();
+    for op in &plan.overlay_ops {
+        add_target(&mut targets, &op.target, mechanism, &op.modules());
+    }
+    for (target, modules) in plan.magic_targets() {
+        add_target(&mut targets, &target, "magic", &modules);
+    }
+    targets
+}
+
+fn diff_targets(
+    before: &BTreeMap<String, TargetSnapshot>,
+    after: &BTreeMap<String, TargetSnapshot>,
+) -> Vec<TargetChange> {
+    let mut targets = Vec::new();
+    for (target, old) in before {
+        match after.get(target) {
+            Some(new) => {
+                let changes = target_changes(old, new);
+                if !changes.is_empty() {
+                    targets.push(TargetChange {
+                        target: target.to_string(),
+                        changes,
+                        before: Some(old.clone()),
+                        after: Some(new.clone()),
+                    });
+                }
+            }
+            None => targets.push(TargetChange {
+                target: target.to_string(),
+                changes: vec!["removed"],
+                before: Some(old.clone()),
+                after: None,
+            }),
+        }
+    }
+
+    for (target, new) in after {
+        if !before.contains_key(target) {
+            targets.push(TargetChange {
+                target: target.to_string(),
+                changes: vec!["added"],
+                before: None,
+                after: Some(new.clone()),
+            });
+        }
+    }
+
+    targets.sort_by(|a, b| a.target.cmp(&b.target));
+    targets
+}
+
+pub fn diff(plan: &MountPlan, state: &RuntimeState) -> PlanDiff {
+    let mut before = BTreeMap::new();
+    for outcome in &state.target_outcomes {
+        add_target(
+            &mut before,
+            &outcome.target,
+            &outcome.mechanism,
+            &outcome.modules,
+        );
+    }
+
+    PlanDiff {
+        booted_at: state.timestamp,
+        targets: diff_targets(&before, &plan_targets(plan)),
+        overlay_modules: list_change(&state.overlay_modules, &plan.overlay_module_ids),
+        magic_modules: list_change(&state.magic_modules, &plan.magic_module_ids),
+        active_mounts: list_change(&state.active_mounts, &plan.active_partitions()),
+    }
+}
+
+pub fn compare(before: &MountPlan, after: &MountPlan) -> PlanComparison {
+    PlanComparison {
+        targets: diff_targets(&plan_targets(before), &plan_targets(after)),
+        overlay_modules: list_change(&before.overlay_module_ids, &after.overlay_module_ids),
+        magic_modules: list_change(&before.magic_module_ids, &after.magic_module_ids),
+        active_mounts: list_change(&before.active_partitions(), &after.active_partitions()),
+    }
+}
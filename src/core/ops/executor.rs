@@ -1,22 +1,44 @@
-use std::{collections::HashSet, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
 use anyhow::Result;
 
 use crate::{
     conf::config,
-    core::ops::planner::MountPlan,
+    core::{
+        ops::planner::{MountPlan, OverlayOperation},
+        rw,
+        state::TargetOutcome,
+    },
     defs,
     mount::{
         magic_mount,
         overlayfs::{self, utils::umount_dir},
         umount_mgr,
     },
-    utils,
+    sys::capabilities,
 };
 
 pub struct ExecutionResult {
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub target_outcomes: Vec<TargetOutcome>,
+}
+
+fn fallback_to_magic(
+    op: &OverlayOperation,
+    magic_subtrees: &mut HashMap<String, Vec<String>>,
+    final_magic_ids: &mut HashSet<String>,
+) {
+    for origin in &op.origins {
+        let subtrees = magic_subtrees.entry(origin.module_id.clone()).or_default();
+        if !subtrees.contains(&origin.relative_path) {
+            subtrees.push(origin.relative_path.clone());
+        }
+        final_magic_ids.insert(origin.module_id.clone());
+    }
 }
 
 pub fn execute<P>(plan: &MountPlan, config: &config::Config, tempdir: P) -> Result<ExecutionResult>
@@ -25,34 +47,35 @@ where
 {
     let mut final_magic_ids: HashSet<String> = plan.magic_module_ids.iter().cloned().collect();
     let mut final_overlay_ids: HashSet<String> = HashSet::new();
+    let mut magic_subtrees = plan.magic_subtrees.clone();
+    let mut target_outcomes: Vec<TargetOutcome> = Vec::new();
 
-    if overlayfs::utils::is_overlay_supported()? {
+    if capabilities::get().overlay_supported() {
         log::info!(">> Phase 1: OverlayFS Execution...");
 
-        for op in &plan.overlay_ops {
-            let involved_modules: Vec<String> = op
-                .lowerdirs
-                .iter()
-                .filter_map(|p| utils::extract_module_id(p))
-                .collect();
+        overlayfs::overlayfs::cleanup_staging();
+        rw::warn_unmanaged(config);
 
+        for op in &plan.overlay_ops {
             let lowerdir_strings: Vec<String> = op
                 .lowerdirs
                 .iter()
                 .map(|p| p.display().to_string())
                 .collect();
 
-            let rw_root = Path::new(defs::SYSTEM_RW_DIR);
-            let part_rw = rw_root.join(&op.partition_name);
-            let upper = part_rw.join("upperdir");
-            let work = part_rw.join("workdir");
-
-            let (upper_opt, work_opt) = if upper.exists() && work.exists() {
-                (Some(upper), Some(work))
-            } else {
-                (None, None)
+            let (upper_opt, work_opt) = match rw::prepare(config, &op.partition_name, &op.target) {
+                Some((upper, work)) => (Some(upper), Some(work)),
+                None => (None, None),
             };
 
+            if lowerdir_strings.is_empty() && upper_opt.is_none() {
+                log::info!(
+                    "Skipping {}: no module layers and no writable upperdir",
+                    op.target
+                );
+                continue;
+            }
+
             log::info!(
                 "Mounting {} [OVERLAY] (Layers: {})",
                 op.target,
@@ -77,11 +100,17 @@ where
                 work_opt,
                 upper_opt,
                 &mount_source,
+                &config.overlay_options_for(&op.target),
             ) {
                 Ok(_) => {
-                    for id in involved_modules {
-                        final_overlay_ids.insert(id);
-                    }
+                    final_overlay_ids.extend(op.modules());
+                    target_outcomes.push(TargetOutcome {
+                        target: op.target.clone(),
+                        partition: op.partition_name.clone(),
+                        mechanism: "overlay".to_string(),
+                        modules: op.modules(),
+                        error: None,
+                    });
 
                     #[cfg(any(target_os = "linux", target_os = "android"))]
                     if !config.disable_umount
@@ -100,21 +129,48 @@ where
                         op.target,
                         e
                     );
-                    for id in involved_modules {
-                        final_magic_ids.insert(id);
-                    }
+                    fallback_to_magic(op, &mut magic_subtrees, &mut final_magic_ids);
+                    target_outcomes.push(TargetOutcome {
+                        target: op.target.clone(),
+                        partition: op.partition_name.clone(),
+                        mechanism: "magic_fallback".to_string(),
+                        modules: op.modules(),
+                        error: Some(format!("{:#}", e)),
+                    });
                 }
             }
         }
-
-        final_overlay_ids.retain(|id| !final_magic_ids.contains(id));
     } else {
         log::warn!("Your device does not support overlay; it has fallen back to Magic mount.");
-        final_magic_ids.extend(final_overlay_ids.clone());
+        for op in &plan.overlay_ops {
+            fallback_to_magic(op, &mut magic_subtrees, &mut final_magic_ids);
+            target_outcomes.push(TargetOutcome {
+                target: op.target.clone(),
+                partition: op.partition_name.clone(),
+                mechanism: "magic_fallback".to_string(),
+                modules: op.modules(),
+                error: Some("overlayfs is not supported".to_string()),
+            });
+        }
+    }
+
+    for (target, modules) in plan.magic_targets() {
+        target_outcomes.push(TargetOutcome {
+            partition: target.split('/').nth(1).unwrap_or_default().to_string(),
+            target,
+            mechanism: "magic".to_string(),
+            modules,
+            error: None,
+        });
     }
 
     let mut magic_queue: Vec<String> = final_magic_ids.iter().cloned().collect();
-    magic_queue.sort();
+    magic_queue.sort_by_key(|id| {
+        plan.module_order
+            .iter()
+            .position(|o| o == id)
+            .unwrap_or(usize::MAX)
+    });
 
     if !magic_queue.is_empty() {
         let magic_ws_path = tempdir.as_ref().join("magic_workspace");
@@ -135,18 +191,26 @@ where
         }
 
         let module_dir = tempdir.as_ref();
-        let magic_need_ids: HashSet<String> = magic_queue.iter().cloned().collect();
 
         if let Err(e) = magic_mount::magic_mount(
             &magic_ws_path,
             module_dir,
             &config.mountsource,
             &config.partitions,
-            magic_need_ids,
+            &magic_queue,
+            &plan.module_rules,
+            &magic_subtrees,
             !config.disable_umount,
         ) {
             log::error!("Magic Mount critical failure: {:#}", e);
             final_magic_ids.clear();
+            for outcome in target_outcomes
+                .iter_mut()
+                .filter(|o| matches!(o.mechanism.as_str(), "magic" | "magic_fallback"))
+            {
+                outcome.mechanism = "failed".to_string();
+                outcome.error = Some(format!("{:#}", e));
+            }
         }
     }
 
@@ -177,5 +241,6 @@ where
     Ok(ExecutionResult {
         overlay_module_ids: result_overlay,
         magic_module_ids: result_magic,
+        target_outcomes,
     })
 }
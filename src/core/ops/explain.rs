@@ -0,0 +1,301 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use procfs::process::Process;
+use serde::Serialize;
+
+use super::planner::{self, MountPlan};
+use crate::{
+    conf::config,
+    core::state::{RuntimeState, TargetOutcome},
+    mount::node::{Node, NodeFileType},
+};
+
+#[derive(Debug, Serialize)]
+pub struct Provider {
+    pub module_id: String,
+    pub relative_path: String,
+    pub mechanism: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer_index: Option<usize>,
+    pub file_type: String,
+    pub rule: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpaqueDir {
+    pub module_id: String,
+    pub relative_path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LiveMount {
+    pub mount_point: String,
+    pub fs_type: String,
+    pub source: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Explanation {
+    pub path: String,
+    pub winner: String,
+    pub stock_hidden: bool,
+    pub providers: Vec<Provider>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub opaque_dirs: Vec<OpaqueDir>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_outcome: Option<TargetOutcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub live_mount: Option<LiveMount>,
+}
+
+fn resolve_path(path: &Path) -> PathBuf {
+    let path = Path::new("/").join(path);
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map(|p| p.join(name))
+            .unwrap_or(path.clone()),
+        _ => path,
+    }
+}
+
+fn relative_keys(path: &Path) -> Vec<String> {
+    let relative = path
+        .strip_prefix("/")
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string();
+
+    let mut keys = vec![relative.clone()];
+    if relative != "system" && !relative.starts_with("system/") {
+        keys.push(format!("system/{relative}"));
+    }
+    keys
+}
+
+fn is_within(path: &str, base: &str) -> bool {
+    path == base
+        || path
+            .strip_prefix(base)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn join_relative(base: &str, sub: &Path) -> String {
+    if sub.as_os_str().is_empty() {
+        base.to_string()
+    } else {
+        format!("{}/{}", base, sub.to_string_lossy())
+    }
+}
+
+fn file_type_of(path: &Path) -> Option<NodeFileType> {
+    fs::symlink_metadata(path)
+        .ok()
+        .map(|m| NodeFileType::from(m.file_type()))
+}
+
+fn rule_for(plan: &MountPlan, module_id: &str, relative_path: &str) -> String {
+    if let Some(decision) = plan
+        .auto_decisions
+        .iter()
+        .find(|d| d.module_id == module_id && d.relative_path == relative_path)
+    {
+        return format!("auto -> {}: {}", decision.mode.as_str(), decision.reason);
+    }
+
+    let Some(rules) = plan.module_rules.get(module_id) else {
+        return "default".to_string();
+    };
+
+    match rules.matching_rule(relative_path) {
+        Some((pattern, mode)) => format!("paths[\"{}\"] = {}", pattern, mode.as_str()),
+        None => format!("default_mode = {}", rules.default_mode.as_str()),
+    }
+}
+
+fn collect_opaque(
+    root: &Path,
+    sub: &Path,
+    module_id: &str,
+    base: &str,
+    opaque_dirs: &mut Vec<OpaqueDir>,
+) {
+    for prefix in sub.ancestors() {
+        let dir = root.join(prefix);
+        if dir.is_dir() && !dir.is_symlink() && Node::dir_is_replace(&dir) {
+            opaque_dirs.push(OpaqueDir {
+                module_id: module_id.to_string(),
+                relative_path: join_relative(base, prefix),
+            });
+        }
+    }
+}
+
+fn live_mount(path: &Path) -> Option<LiveMount> {
+    Process::myself()
+        .and_then(|p| p.mountinfo())
+        .ok()?
+        .into_iter()
+        .filter(|m| path.starts_with(&m.mount_point))
+        .max_by_key(|m| m.mount_point.components().count())
+        .map(|m| LiveMount {
+            mount_point: m.mount_point.display().to_string(),
+            fs_type: m.fs_type,
+            source: m.mount_source.unwrap_or_default(),
+        })
+}
+
+pub fn explain(plan: &MountPlan, path: &Path, state: Option<&RuntimeState>) -> Explanation {
+    let path = resolve_path(path);
+    let keys = relative_keys(&path);
+
+    let mut providers = Vec::new();
+    let mut opaque_dirs = Vec::new();
+
+    for module_id in &plan.module_order {
+        let (Some(subtrees), Some(module_path)) = (
+            plan.magic_subtrees.get(module_id),
+            plan.module_paths.get(module_id),
+        ) else {
+            continue;
+        };
+        let rules = plan.module_rules.get(module_id);
+
+        for key in &keys {
+            let Some(subtree) = subtrees.iter().find(|s| is_within(key, s)) else {
+                continue;
+            };
+
+            let ignored = rules.is_some_and(|r| {
+                Path::new(key)
+                    .ancestors()
+                    .take_while(|p| p.starts_with(subtree))
+                    .any(|p| r.get_mode(&p.to_string_lossy()) == config::MountMode::Ignore)
+            });
+            if ignored {
+                continue;
+            }
+
+            let sub = Path::new(key)
+                .strip_prefix(subtree)
+                .unwrap_or(Path::new(""));
+            collect_opaque(
+                &module_path.join(subtree),
+                sub,
+                module_id,
+                subtree,
+                &mut opaque_dirs,
+            );
+
+            let Some(file_type) = file_type_of(&module_path.join(key)) else {
+                continue;
+            };
+            providers.push(Provider {
+                module_id: module_id.clone(),
+                relative_path: key.clone(),
+                mechanism: "magic".to_string(),
+                target: None,
+                layer_index: None,
+                file_type: planner::file_type_name(&file_type).to_string(),
+                rule: rule_for(plan, module_id, subtree),
+            });
+        }
+    }
+
+    for op in plan.overlay_ops.iter().rev() {
+        let Ok(sub) = path.strip_prefix(&op.target) else {
+            continue;
+        };
+
+        for (index, (lowerdir, origin)) in op.lowerdirs.iter().zip(&op.origins).enumerate() {
+            collect_opaque(
+                lowerdir,
+                sub,
+                &origin.module_id,
+                &origin.relative_path,
+                &mut opaque_dirs,
+            );
+
+            let Some(file_type) = file_type_of(&lowerdir.join(sub)) else {
+                continue;
+            };
+            providers.push(Provider {
+                module_id: origin.module_id.clone(),
+                relative_path: join_relative(&origin.relative_path, sub),
+                mechanism: "overlay".to_string(),
+                target: Some(op.target.clone()),
+                layer_index: Some(index),
+                file_type: planner::file_type_name(&file_type).to_string(),
+                rule: rule_for(plan, &origin.module_id, &origin.relative_path),
+            });
+        }
+    }
+
+    for suppressed in &plan.suppressed {
+        for key in &keys {
+            if !is_within(key, &suppressed.relative_path) {
+                continue;
+            }
+
+            let source = match Path::new(key).strip_prefix(&suppressed.relative_path) {
+                Ok(sub) if !sub.as_os_str().is_empty() => suppressed.source.join(sub),
+                _ => suppressed.source.clone(),
+            };
+            let Some(file_type) = file_type_of(&source) else {
+                continue;
+            };
+            let rule = config::conflict_policy_winner(&plan.conflict_policy, key)
+                .map(|(pattern, winner)| format!("conflict_policy[\"{}\"] = {}", pattern, winner))
+                .unwrap_or_default();
+
+            providers.push(Provider {
+                module_id: suppressed.module_id.clone(),
+                relative_path: key.clone(),
+                mechanism: "suppressed".to_string(),
+                target: None,
+                layer_index: None,
+                file_type: planner::file_type_name(&file_type).to_string(),
+                rule,
+            });
+        }
+    }
+
+    let stock_hidden = !opaque_dirs.is_empty();
+    let winner = providers
+        .iter()
+        .find(|p| p.mechanism != "suppressed")
+        .map(|p| p.module_id.clone())
+        .unwrap_or_else(|| if stock_hidden { "none" } else { "stock" }.to_string());
+
+    let policy = keys.iter().find_map(|key| {
+        config::conflict_policy_winner(&plan.conflict_policy, key)
+            .map(|(pattern, winner)| format!("{} -> {}", pattern, winner))
+    });
+
+    let boot_outcome = state.and_then(|state| {
+        state
+            .target_outcomes
+            .iter()
+            .filter(|o| path.starts_with(&o.target))
+            .max_by_key(|o| o.target.len())
+            .cloned()
+    });
+
+    Explanation {
+        path: path.display().to_string(),
+        winner,
+        stock_hidden,
+        providers,
+        opaque_dirs,
+        policy,
+        live_mount: live_mount(&path),
+        boot_outcome,
+    }
+}
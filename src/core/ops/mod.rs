@@ -1,3 +1,6 @@
+pub mod diff;
 pub mod executor;
+pub mod explain;
 pub mod planner;
+pub mod simulate;
 pub mod sync;
@@ -1,32 +1,107 @@
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fs,
+    ops::Bound,
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
 };
 
 use anyhow::Result;
+use procfs::process::Process;
 use rayon::prelude::*;
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 use walkdir::WalkDir;
 
 use crate::{
     conf::config,
     core::inventory::{Module, MountMode},
-    defs, utils,
+    defs,
+    mount::{
+        node::{Node, NodeFileType},
+        overlayfs::overlayfs::max_direct_layers,
+    },
+    sys::capabilities,
+    utils,
 };
 
-#[derive(Debug, Clone)]
+const UNSUPPORTED_LOWER_FS: &[&str] = &["vfat", "msdos", "exfat", "ntfs3", "sdcardfs"];
+
+#[derive(Debug, Clone, Serialize)]
 pub struct OverlayOperation {
     pub partition_name: String,
     pub target: String,
     pub lowerdirs: Vec<PathBuf>,
+    pub origins: Vec<LayerOrigin>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerOrigin {
+    pub module_id: String,
+    pub relative_path: String,
+}
+
+impl OverlayOperation {
+    pub fn modules(&self) -> Vec<String> {
+        let mut modules: Vec<String> = Vec::new();
+        for origin in &self.origins {
+            if !modules.contains(&origin.module_id) {
+                modules.push(origin.module_id.clone());
+            }
+        }
+        modules
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct MountPlan {
     pub overlay_ops: Vec<OverlayOperation>,
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub module_order: Vec<String>,
+    pub module_rules: HashMap<String, config::ModuleRules>,
+    pub magic_subtrees: HashMap<String, Vec<String>>,
+    pub module_paths: HashMap<String, PathBuf>,
+    pub conflict_policy: HashMap<String, String>,
+    pub suppressed: Vec<SuppressedPath>,
+    pub diagnostics: Vec<DiagnosticIssue>,
+    pub auto_decisions: Vec<AutoDecision>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SuppressedPath {
+    pub module_id: String,
+    pub relative_path: String,
+    pub source: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoDecision {
+    pub module_id: String,
+    pub relative_path: String,
+    pub target: String,
+    pub mode: MountMode,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictKind {
+    Identical,
+    Divergent,
+    Type,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Contender {
+    pub module_id: String,
+    pub mechanism: String,
+    pub file_type: String,
+    pub size: u64,
+    pub mode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selinux_context: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -34,19 +109,277 @@ pub struct ConflictEntry {
     pub partition: String,
     pub relative_path: String,
     pub contending_modules: Vec<String>,
+    pub kind: ConflictKind,
+    pub winner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    pub contenders: Vec<Contender>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+struct LayerEntry {
+    relative_path: String,
+    source: PathBuf,
+    file_type: NodeFileType,
+    opaque: bool,
+}
+
+struct PathClaim {
+    entry: LayerEntry,
+    module_id: String,
+    partition: String,
+    mechanism: &'static str,
+    rank: (usize, usize, usize),
+}
+
+impl PathClaim {
+    fn hides(&self, other: &PathClaim) -> bool {
+        if other.module_id == self.module_id
+            || other.rank <= self.rank
+            || self.is_suppressed()
+            || other.is_suppressed()
+        {
+            return false;
+        }
+
+        match self.entry.file_type {
+            NodeFileType::Whiteout => true,
+            NodeFileType::Directory => self.entry.opaque && other.mechanism == "overlay",
+            _ => false,
+        }
+    }
+
+    fn is_suppressed(&self) -> bool {
+        self.mechanism == "suppressed"
+    }
+}
+
+pub fn file_type_name(file_type: &NodeFileType) -> &'static str {
+    match file_type {
+        NodeFileType::RegularFile => "file",
+        NodeFileType::Directory => "directory",
+        NodeFileType::Symlink => "symlink",
+        NodeFileType::Whiteout => "whiteout",
+    }
+}
+
+fn contender(claim: &PathClaim) -> Contender {
+    let source = &claim.entry.source;
+    let metadata = fs::symlink_metadata(source).ok();
+    let is_file = claim.entry.file_type == NodeFileType::RegularFile;
+    Contender {
+        module_id: claim.module_id.clone(),
+        mechanism: claim.mechanism.to_string(),
+        file_type: file_type_name(&claim.entry.file_type).to_string(),
+        size: metadata.as_ref().map_or(0, |m| m.len()),
+        mode: format!("{:04o}", metadata.map_or(0, |m| m.mode() & 0o7777)),
+        selinux_context: utils::lgetfilecon(source).ok(),
+        hash: is_file.then(|| utils::file_sha256(source).ok()).flatten(),
+    }
+}
+
+fn conflict_entry(
+    relative_path: String,
+    claims: &[PathClaim],
+    policy: Option<&str>,
+) -> ConflictEntry {
+    let contenders: Vec<Contender> = claims.iter().map(contender).collect();
+
+    let mixed_types = claims
+        .iter()
+        .any(|c| c.entry.file_type != claims[0].entry.file_type);
+    let identical = contenders
+        .iter()
+        .all(|c| c.hash.is_some() && c.hash == contenders[0].hash);
+
+    ConflictEntry {
+        partition: claims[0].partition.clone(),
+        relative_path,
+        contending_modules: claims.iter().map(|c| c.module_id.clone()).collect(),
+        kind: if mixed_types {
+            ConflictKind::Type
+        } else if identical {
+            ConflictKind::Identical
+        } else {
+            ConflictKind::Divergent
+        },
+        winner: claims
+            .iter()
+            .find(|c| !c.is_suppressed())
+            .map_or(defs::CONFLICT_POLICY_STOCK, |c| c.module_id.as_str())
+            .to_string(),
+        policy: policy.map(|p| p.to_string()),
+        contenders,
+    }
+}
+
+fn mount_fs_type<'a>(mounts: &'a [(PathBuf, String)], path: &Path) -> Option<&'a str> {
+    mounts
+        .iter()
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .map(|(_, fs_type)| fs_type.as_str())
+}
+
+fn scan_layer(
+    module_id: &str,
+    root: &Path,
+    relative: &str,
+    rules: Option<&config::ModuleRules>,
+    diagnostics: &mut Vec<DiagnosticIssue>,
+) -> Vec<LayerEntry> {
+    let key_of = |path: &Path| match path.strip_prefix(root) {
+        Ok(rel) if !rel.as_os_str().is_empty() => {
+            format!("{}/{}", relative, rel.to_string_lossy())
+        }
+        _ => relative.to_string(),
+    };
+
+    let check_selinux = utils::selinux_enabled();
+    let mut unlabeled = 0;
+
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(root)
+        .follow_root_links(false)
+        .into_iter()
+        .filter_entry(|e| {
+            rules.is_none_or(|r| !matches!(r.get_mode(&key_of(e.path())), MountMode::Ignore))
+        })
+        .flatten()
+    {
+        if entry.path_is_symlink()
+            && let Some(target) = utils::dead_absolute_symlink(entry.path())
+        {
+            diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                code: DiagnosticCode::DeadSymlink,
+                context: module_id.to_string(),
+                message: format!(
+                    "Dead absolute symlink: {} -> {}",
+                    entry.path().display(),
+                    target.display()
+                ),
+                modules: Vec::new(),
+            });
+        }
+
+        if entry.file_name() == defs::REPLACE_DIR_FILE_NAME {
+            continue;
+        }
+
+        if let Some(mode) = entry.metadata().ok().as_ref().and_then(utils::setid_mode) {
+            diagnostics.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                code: DiagnosticCode::SetidBit,
+                context: module_id.to_string(),
+                message: format!(
+                    "setuid/setgid bit set on {} (mode {:o})",
+                    entry.path().display(),
+                    mode
+                ),
+                modules: Vec::new(),
+            });
+        }
+
+        if check_selinux && utils::lacks_selinux_label(entry.path()) {
+            unlabeled += 1;
+        }
+
+        let file_type = NodeFileType::from(entry.file_type());
+        let opaque = file_type == NodeFileType::Directory && Node::dir_is_replace(entry.path());
+        entries.push(LayerEntry {
+            relative_path: key_of(entry.path()),
+            source: entry.into_path(),
+            file_type,
+            opaque,
+        });
+    }
+
+    if unlabeled > 0 {
+        diagnostics.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            code: DiagnosticCode::MissingSelinuxLabel,
+            context: module_id.to_string(),
+            message: format!(
+                "{} entries under {} have no SELinux label",
+                unlabeled,
+                root.display()
+            ),
+            modules: Vec::new(),
+        });
+    }
+
+    entries
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub enum DiagnosticLevel {
     Warning,
     Critical,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticCode {
+    AbsentPartition,
+    CrossPartitionSymlink,
+    DanglingPathRule,
+    DeadSymlink,
+    DeviceNode,
+    IdMismatch,
+    InvalidRules,
+    LayerLimit,
+    MissingSelinuxLabel,
+    MissingTarget,
+    ModuleSkipped,
+    OpaqueShadow,
+    SetidBit,
+    TargetNotDirectory,
+    UnknownPartition,
+    UnknownPolicyModule,
+    UnknownRuleKey,
+    UnsupportedLowerFs,
+    WhiteoutDelete,
+}
+
+impl DiagnosticCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AbsentPartition => "absent-partition",
+            Self::CrossPartitionSymlink => "cross-partition-symlink",
+            Self::DanglingPathRule => "dangling-path-rule",
+            Self::DeadSymlink => "dead-symlink",
+            Self::DeviceNode => "device-node",
+            Self::IdMismatch => "id-mismatch",
+            Self::InvalidRules => "invalid-rules",
+            Self::LayerLimit => "layer-limit",
+            Self::MissingSelinuxLabel => "missing-selinux-label",
+            Self::MissingTarget => "missing-target",
+            Self::ModuleSkipped => "module-skipped",
+            Self::OpaqueShadow => "opaque-shadow",
+            Self::SetidBit => "setid-bit",
+            Self::TargetNotDirectory => "target-not-directory",
+            Self::UnknownPartition => "unknown-partition",
+            Self::UnknownPolicyModule => "unknown-policy-module",
+            Self::UnknownRuleKey => "unknown-rule-key",
+            Self::UnsupportedLowerFs => "unsupported-lower-fs",
+            Self::WhiteoutDelete => "whiteout-delete",
+        }
+    }
+}
+
+impl Serialize for DiagnosticCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct DiagnosticIssue {
     pub level: DiagnosticLevel,
+    pub code: DiagnosticCode,
     pub context: String,
     pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<String>,
 }
 
 #[derive(Debug, Default)]
@@ -56,77 +389,265 @@ pub struct AnalysisReport {
 }
 
 impl MountPlan {
+    pub fn active_partitions(&self) -> Vec<String> {
+        let mut partitions: Vec<String> = self
+            .overlay_ops
+            .iter()
+            .map(|op| op.partition_name.clone())
+            .collect();
+        partitions.sort();
+        partitions.dedup();
+        partitions
+    }
+
+    pub fn magic_targets(&self) -> BTreeMap<String, Vec<String>> {
+        let mut targets: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for module_id in &self.module_order {
+            for subtree in self.magic_subtrees.get(module_id).into_iter().flatten() {
+                let modules = targets.entry(format!("/{}", subtree)).or_default();
+                if !modules.contains(module_id) {
+                    modules.push(module_id.clone());
+                }
+            }
+        }
+        targets
+    }
+
     pub fn analyze(&self) -> AnalysisReport {
-        let results: Vec<(Vec<ConflictEntry>, Vec<DiagnosticIssue>)> = self
+        let max_layers = max_direct_layers();
+        let mounts: Vec<(PathBuf, String)> = Process::myself()
+            .and_then(|p| p.mountinfo())
+            .map(|infos| {
+                infos
+                    .into_iter()
+                    .map(|m| (m.mount_point, m.fs_type))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let overlay_results: Vec<(Vec<PathClaim>, Vec<DiagnosticIssue>)> = self
             .overlay_ops
             .par_iter()
             .map(|op| {
-                let mut local_conflicts = Vec::new();
-                let mut local_diagnostics = Vec::new();
-                let mut file_map: HashMap<String, Vec<String>> = HashMap::new();
+                let mut claims = Vec::new();
+                let mut diagnostics = Vec::new();
 
                 if !Path::new(&op.target).exists() {
-                    local_diagnostics.push(DiagnosticIssue {
+                    diagnostics.push(DiagnosticIssue {
                         level: DiagnosticLevel::Critical,
+                        code: DiagnosticCode::MissingTarget,
                         context: op.partition_name.clone(),
                         message: format!("Target mount point does not exist: {}", op.target),
+                        modules: op.modules(),
+                    });
+                }
+
+                if op.lowerdirs.len() > max_layers {
+                    diagnostics.push(DiagnosticIssue {
+                        level: DiagnosticLevel::Warning,
+                        code: DiagnosticCode::LayerLimit,
+                        context: op.partition_name.clone(),
+                        message: format!(
+                            "{} has {} layers, above the direct limit of {}; they will be stacked",
+                            op.target,
+                            op.lowerdirs.len(),
+                            max_layers
+                        ),
+                        modules: op.modules(),
                     });
                 }
 
-                for layer_path in &op.lowerdirs {
+                let depth = Path::new(&op.target).components().count();
+
+                for (index, layer_path) in op.lowerdirs.iter().enumerate() {
                     if !layer_path.exists() {
                         continue;
                     }
 
-                    let module_id =
-                        utils::extract_module_id(layer_path).unwrap_or_else(|| "UNKNOWN".into());
-
-                    for entry in WalkDir::new(layer_path).min_depth(1).into_iter().flatten() {
-                        if entry.path_is_symlink()
-                            && let Ok(target) = std::fs::read_link(entry.path())
-                            && target.is_absolute()
-                            && !target.exists()
-                        {
-                            local_diagnostics.push(DiagnosticIssue {
-                                level: DiagnosticLevel::Warning,
-                                context: module_id.clone(),
-                                message: format!(
-                                    "Dead absolute symlink: {} -> {}",
-                                    entry.path().display(),
-                                    target.display()
-                                ),
-                            });
-                        }
+                    let origin = op.origins.get(index);
+                    let module_id = origin
+                        .map(|o| o.module_id.clone())
+                        .or_else(|| utils::extract_module_id(layer_path))
+                        .unwrap_or_else(|| "UNKNOWN".into());
+                    let layer_relative = origin
+                        .map(|o| o.relative_path.clone())
+                        .unwrap_or_else(|| op.partition_name.clone());
 
-                        if !entry.file_type().is_file() {
-                            continue;
-                        }
-
-                        if let Ok(rel) = entry.path().strip_prefix(layer_path) {
-                            let rel_str = rel.to_string_lossy().to_string();
-                            file_map.entry(rel_str).or_default().push(module_id.clone());
-                        }
+                    if let Some(fs_type) = mount_fs_type(&mounts, layer_path)
+                        && UNSUPPORTED_LOWER_FS.contains(&fs_type)
+                    {
+                        diagnostics.push(DiagnosticIssue {
+                            level: DiagnosticLevel::Critical,
+                            code: DiagnosticCode::UnsupportedLowerFs,
+                            context: module_id.clone(),
+                            message: format!(
+                                "Layer {} is on {}, which overlayfs cannot use as a lowerdir",
+                                layer_path.display(),
+                                fs_type
+                            ),
+                            modules: Vec::new(),
+                        });
                     }
+
+                    let entries = scan_layer(
+                        &module_id,
+                        layer_path,
+                        &layer_relative,
+                        None,
+                        &mut diagnostics,
+                    );
+                    claims.extend(entries.into_iter().map(|entry| PathClaim {
+                        entry,
+                        module_id: module_id.clone(),
+                        partition: op.partition_name.clone(),
+                        mechanism: "overlay",
+                        rank: (1, usize::MAX - depth, index),
+                    }));
                 }
 
-                for (rel_path, modules) in file_map {
-                    if modules.len() > 1 {
-                        local_conflicts.push(ConflictEntry {
-                            partition: op.partition_name.clone(),
-                            relative_path: rel_path,
-                            contending_modules: modules,
-                        });
+                (claims, diagnostics)
+            })
+            .collect();
+
+        let magic_modules: Vec<(usize, &String, &Vec<String>)> = self
+            .module_order
+            .iter()
+            .enumerate()
+            .filter_map(|(position, id)| {
+                self.magic_subtrees
+                    .get(id)
+                    .map(|subtrees| (position, id, subtrees))
+            })
+            .collect();
+
+        let magic_results: Vec<(Vec<PathClaim>, Vec<DiagnosticIssue>)> = magic_modules
+            .into_par_iter()
+            .map(|(position, module_id, subtrees)| {
+                let mut claims = Vec::new();
+                let mut diagnostics = Vec::new();
+
+                let Some(module_path) = self.module_paths.get(module_id) else {
+                    return (claims, diagnostics);
+                };
+                let rules = self.module_rules.get(module_id);
+
+                for relative in subtrees {
+                    let source = module_path.join(relative);
+                    if fs::symlink_metadata(&source).is_err() {
+                        continue;
                     }
+
+                    let partition = relative.split('/').next().unwrap_or_default();
+                    let entries = scan_layer(module_id, &source, relative, rules, &mut diagnostics);
+                    claims.extend(entries.into_iter().map(|entry| PathClaim {
+                        entry,
+                        module_id: module_id.clone(),
+                        partition: partition.to_string(),
+                        mechanism: "magic",
+                        rank: (0, position, 0),
+                    }));
                 }
 
-                (local_conflicts, local_diagnostics)
+                (claims, diagnostics)
             })
             .collect();
 
-        let mut report = AnalysisReport::default();
-        for (c, d) in results {
-            report.conflicts.extend(c);
-            report.diagnostics.extend(d);
+        let suppressed_claims = self.suppressed.iter().filter_map(|s| {
+            let metadata = fs::symlink_metadata(&s.source).ok()?;
+            let position = self.module_order.iter().position(|id| *id == s.module_id);
+            Some(PathClaim {
+                entry: LayerEntry {
+                    relative_path: s.relative_path.clone(),
+                    source: s.source.clone(),
+                    file_type: NodeFileType::from(metadata.file_type()),
+                    opaque: false,
+                },
+                module_id: s.module_id.clone(),
+                partition: s
+                    .relative_path
+                    .split('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                mechanism: "suppressed",
+                rank: (2, position.unwrap_or(usize::MAX), 0),
+            })
+        });
+
+        let mut report = AnalysisReport {
+            diagnostics: self.diagnostics.clone(),
+            ..Default::default()
+        };
+        let mut path_map: BTreeMap<String, Vec<PathClaim>> = BTreeMap::new();
+        for (claims, diagnostics) in overlay_results.into_iter().chain(magic_results) {
+            report.diagnostics.extend(diagnostics);
+            for claim in claims {
+                path_map
+                    .entry(claim.entry.relative_path.clone())
+                    .or_default()
+                    .push(claim);
+            }
+        }
+
+        for claim in suppressed_claims {
+            path_map
+                .entry(claim.entry.relative_path.clone())
+                .or_default()
+                .push(claim);
+        }
+
+        for claims in path_map.values_mut() {
+            claims.sort_by_key(|c| c.rank);
+            let mut seen = HashSet::new();
+            claims.retain(|c| seen.insert(c.module_id.clone()));
+        }
+
+        for (relative_path, claims) in &path_map {
+            let active: Vec<&PathClaim> = claims.iter().filter(|c| !c.is_suppressed()).collect();
+            let types: HashSet<&NodeFileType> = active
+                .iter()
+                .map(|c| &c.entry.file_type)
+                .filter(|t| **t != NodeFileType::Whiteout)
+                .collect();
+            let policy = config::conflict_policy_winner(&self.conflict_policy, relative_path)
+                .filter(|_| active.len() < claims.len())
+                .map(|(pattern, _)| pattern);
+
+            if types.len() > 1
+                || policy.is_some()
+                || (active.len() > 1
+                    && active
+                        .iter()
+                        .all(|c| c.entry.file_type == NodeFileType::RegularFile))
+            {
+                report
+                    .conflicts
+                    .push(conflict_entry(relative_path.clone(), claims, policy));
+            }
+
+            for cover in claims
+                .iter()
+                .filter(|c| c.entry.opaque || c.entry.file_type == NodeFileType::Whiteout)
+            {
+                report
+                    .diagnostics
+                    .extend(shadow_diagnostics(&path_map, relative_path, cover));
+            }
+        }
+
+        for (pattern, winner) in &self.conflict_policy {
+            if winner != defs::CONFLICT_POLICY_STOCK && !self.module_order.contains(winner) {
+                report.diagnostics.push(DiagnosticIssue {
+                    level: DiagnosticLevel::Warning,
+                    code: DiagnosticCode::UnknownPolicyModule,
+                    context: winner.clone(),
+                    message: format!(
+                        "Conflict policy for {} names a module that is not installed",
+                        pattern
+                    ),
+                    modules: Vec::new(),
+                });
+            }
         }
 
         report.conflicts.sort_by(|a, b| {
@@ -139,10 +660,135 @@ impl MountPlan {
     }
 }
 
+fn shadow_diagnostics(
+    path_map: &BTreeMap<String, Vec<PathClaim>>,
+    relative_path: &str,
+    cover: &PathClaim,
+) -> Vec<DiagnosticIssue> {
+    let below = (
+        Bound::Excluded(format!("{relative_path}/")),
+        Bound::Excluded(format!("{relative_path}0")),
+    );
+
+    let mut hidden: BTreeMap<&str, usize> = BTreeMap::new();
+    for claim in path_map
+        .get(relative_path)
+        .into_iter()
+        .chain(path_map.range::<String, _>(below).map(|(_, claims)| claims))
+        .flatten()
+        .filter(|c| {
+            !matches!(
+                c.entry.file_type,
+                NodeFileType::Directory | NodeFileType::Whiteout
+            ) && cover.hides(c)
+        })
+    {
+        *hidden.entry(claim.module_id.as_str()).or_default() += 1;
+    }
+
+    hidden
+        .into_iter()
+        .map(|(module_id, count)| DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            code: if cover.entry.file_type == NodeFileType::Whiteout {
+                DiagnosticCode::WhiteoutDelete
+            } else {
+                DiagnosticCode::OpaqueShadow
+            },
+            context: module_id.to_string(),
+            message: if cover.entry.file_type == NodeFileType::Whiteout {
+                format!(
+                    "Whiteout {} from {} deletes {} entries",
+                    relative_path, cover.module_id, count
+                )
+            } else {
+                format!(
+                    "Opaque directory {} from {} hides {} entries",
+                    relative_path, cover.module_id, count
+                )
+            },
+            modules: vec![cover.module_id.clone()],
+        })
+        .collect()
+}
+
+struct AutoLayer {
+    module_id: String,
+    relative_path: String,
+    target: PathBuf,
+    source: PathBuf,
+}
+
+fn has_nested_mounts(mount_points: &[PathBuf], target: &Path) -> bool {
+    mount_points
+        .iter()
+        .any(|mp| mp != target && mp.starts_with(target))
+}
+
+fn policy_rules(
+    config: &config::Config,
+    module: &Module,
+    content_path: &Path,
+    suppressed: &mut Vec<SuppressedPath>,
+) -> config::ModuleRules {
+    let mut rules = module.rules.clone();
+    if config.conflict_policy.is_empty() {
+        return rules;
+    }
+
+    let mut walker = WalkDir::new(content_path).min_depth(1).into_iter();
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Ok(relative) = entry.path().strip_prefix(content_path) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().to_string();
+        let is_dir = entry.file_type().is_dir();
+
+        if entry.depth() == 1
+            && (!is_dir
+                || !(defs::BUILTIN_PARTITIONS.contains(&relative.as_str())
+                    || config.partitions.contains(&relative)))
+        {
+            if is_dir {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+
+        match config::conflict_policy_winner(&config.conflict_policy, &relative) {
+            Some((_, winner)) if winner != module.id => {}
+            _ => {
+                if is_dir
+                    && !config::conflict_policy_reaches_below(&config.conflict_policy, &relative)
+                {
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+        }
+
+        if is_dir {
+            walker.skip_current_dir();
+        }
+        rules.paths.insert(relative.clone(), MountMode::Ignore);
+        suppressed.push(SuppressedPath {
+            module_id: module.id.clone(),
+            relative_path: relative,
+            source: entry.into_path(),
+        });
+    }
+
+    rules
+}
+
 struct ProcessingItem {
     module_source: PathBuf,
     system_target: PathBuf,
     partition_label: String,
+    relative_path: String,
 }
 
 pub fn generate(
@@ -152,21 +798,52 @@ pub fn generate(
 ) -> Result<MountPlan> {
     let mut plan = MountPlan::default();
 
-    let mut overlay_groups: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let mut overlay_groups: HashMap<PathBuf, Vec<(PathBuf, LayerOrigin)>> = HashMap::new();
 
-    let mut overlay_ids = HashSet::new();
-    let mut magic_ids = HashSet::new();
+    let mut magic_subtrees: HashMap<String, Vec<String>> = HashMap::new();
+    let mut auto_layers: Vec<AutoLayer> = Vec::new();
+    let mut effective_rules: HashMap<String, config::ModuleRules> = HashMap::new();
+
+    let overlay_supported = capabilities::get().overlay_supported();
+    let mount_points: Vec<PathBuf> = Process::myself()
+        .and_then(|p| p.mountinfo())
+        .map(|infos| infos.into_iter().map(|m| m.mount_point).collect())
+        .unwrap_or_default();
 
     let sensitive_partitions: HashSet<&str> = defs::SENSITIVE_PARTITIONS.iter().cloned().collect();
 
     for module in modules {
         let mut content_path = storage_root.join(&module.id);
-        if !content_path.exists() {
+        if !content_path.exists() || !module.source_path.starts_with(&config.moduledir) {
             content_path = module.source_path.clone();
         }
         if !content_path.exists() {
             continue;
         }
+        effective_rules
+            .entry(module.id.clone())
+            .or_insert_with(|| policy_rules(config, module, &content_path, &mut plan.suppressed));
+        plan.module_paths.insert(module.id.clone(), content_path);
+    }
+
+    let policy_splits: HashSet<String> = plan
+        .suppressed
+        .iter()
+        .flat_map(|s| {
+            s.relative_path
+                .match_indices('/')
+                .map(|(index, _)| s.relative_path[..index].to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    for module in modules {
+        let (Some(content_path), Some(rules)) = (
+            plan.module_paths.get(&module.id).cloned(),
+            effective_rules.get(&module.id),
+        ) else {
+            continue;
+        };
 
         if let Ok(entries) = fs::read_dir(&content_path) {
             for entry in entries.flatten() {
@@ -183,22 +860,27 @@ pub fn generate(
                     continue;
                 }
 
-                let mode = module.rules.get_mode(&dir_name);
-                if matches!(mode, MountMode::Magic) {
-                    magic_ids.insert(module.id.clone());
-                    continue;
-                }
-                if matches!(mode, MountMode::Ignore) {
-                    continue;
+                if !Path::new("/").join(&dir_name).exists()
+                    && !Path::new("/system").join(&dir_name).exists()
+                {
+                    plan.diagnostics.push(DiagnosticIssue {
+                        level: DiagnosticLevel::Warning,
+                        code: DiagnosticCode::AbsentPartition,
+                        context: module.id.clone(),
+                        message: format!(
+                            "Module ships {}/, but that partition is not present on this device",
+                            dir_name
+                        ),
+                        modules: Vec::new(),
+                    });
                 }
 
-                overlay_ids.insert(module.id.clone());
-
                 let mut queue = VecDeque::new();
                 queue.push_back(ProcessingItem {
                     module_source: path.clone(),
                     system_target: PathBuf::from("/").join(&dir_name),
                     partition_label: dir_name.clone(),
+                    relative_path: dir_name.clone(),
                 });
 
                 while let Some(item) = queue.pop_front() {
@@ -206,9 +888,95 @@ pub fn generate(
                         module_source,
                         system_target,
                         partition_label,
+                        relative_path,
                     } = item;
 
+                    let mode = rules.get_mode(&relative_path);
+
+                    let split = rules.has_overrides_below(&relative_path)
+                        || policy_splits.contains(&relative_path);
+
+                    if split && Node::dir_is_replace(&module_source) {
+                        log::warn!(
+                            "{} in {} is an opaque directory; path rules and conflict_policy below it are not applied",
+                            relative_path,
+                            module.id
+                        );
+                    } else if split {
+                        if let Ok(sub_entries) = fs::read_dir(&module_source) {
+                            for sub_entry in sub_entries.flatten() {
+                                let sub_path = sub_entry.path();
+                                let sub_name = sub_entry.file_name();
+                                if sub_name == defs::REPLACE_DIR_FILE_NAME {
+                                    continue;
+                                }
+                                let sub_relative =
+                                    format!("{}/{}", relative_path, sub_name.to_string_lossy());
+
+                                let sub_target = system_target.join(&sub_name);
+                                let sub_mode = rules.get_mode(&sub_relative);
+
+                                if sub_path.is_dir()
+                                    && (sub_target.exists()
+                                        || rules.has_overrides_below(&sub_relative)
+                                        || policy_splits.contains(&sub_relative)
+                                        || !matches!(sub_mode, MountMode::Overlay))
+                                {
+                                    queue.push_back(ProcessingItem {
+                                        module_source: sub_path,
+                                        system_target: sub_target,
+                                        partition_label: partition_label.clone(),
+                                        relative_path: sub_relative,
+                                    });
+                                } else if !matches!(sub_mode, MountMode::Ignore) {
+                                    if !matches!(sub_mode, MountMode::Magic) {
+                                        plan.auto_decisions.push(AutoDecision {
+                                            module_id: module.id.clone(),
+                                            relative_path: sub_relative.clone(),
+                                            target: sub_target.display().to_string(),
+                                            mode: MountMode::Magic,
+                                            reason: format!(
+                                                "{} is split by path rules or conflict_policy below it",
+                                                relative_path
+                                            ),
+                                        });
+                                    }
+                                    magic_subtrees
+                                        .entry(module.id.clone())
+                                        .or_default()
+                                        .push(sub_relative);
+                                }
+                            }
+                        }
+                        continue;
+                    }
+
+                    match mode {
+                        MountMode::Ignore => continue,
+                        MountMode::Magic => {
+                            magic_subtrees
+                                .entry(module.id.clone())
+                                .or_default()
+                                .push(relative_path);
+                            continue;
+                        }
+                        MountMode::Overlay | MountMode::Auto => {}
+                    }
+
                     if !system_target.exists() {
+                        if matches!(mode, MountMode::Auto) {
+                            plan.auto_decisions.push(AutoDecision {
+                                module_id: module.id.clone(),
+                                relative_path: relative_path.clone(),
+                                target: system_target.display().to_string(),
+                                mode: MountMode::Magic,
+                                reason: "target does not exist".to_string(),
+                            });
+                            magic_subtrees
+                                .entry(module.id.clone())
+                                .or_default()
+                                .push(relative_path);
+                        }
                         continue;
                     }
 
@@ -235,6 +1003,26 @@ pub fn generate(
                         resolved_target
                     };
 
+                    let partition_link = relative_path
+                        .strip_prefix("system/")
+                        .is_some_and(|p| defs::BUILTIN_PARTITIONS.contains(&p));
+                    if system_target.is_symlink()
+                        && !partition_link
+                        && canonical_target.iter().nth(1) != system_target.iter().nth(1)
+                    {
+                        plan.diagnostics.push(DiagnosticIssue {
+                            level: DiagnosticLevel::Warning,
+                            code: DiagnosticCode::CrossPartitionSymlink,
+                            context: module.id.clone(),
+                            message: format!(
+                                "Target {} is a symlink into another partition ({})",
+                                system_target.display(),
+                                canonical_target.display()
+                            ),
+                            modules: Vec::new(),
+                        });
+                    }
+
                     let target_name = canonical_target
                         .file_name()
                         .map(|s| s.to_string_lossy())
@@ -254,29 +1042,160 @@ pub fn generate(
 
                                 queue.push_back(ProcessingItem {
                                     module_source: sub_path,
-                                    system_target: canonical_target.join(sub_name),
+                                    system_target: canonical_target.join(&sub_name),
                                     partition_label: partition_label.clone(),
+                                    relative_path: format!(
+                                        "{}/{}",
+                                        relative_path,
+                                        sub_name.to_string_lossy()
+                                    ),
                                 });
                             }
                         }
                     } else {
-                        overlay_groups
-                            .entry(canonical_target)
-                            .or_default()
-                            .push(module_source);
+                        if matches!(mode, MountMode::Auto) {
+                            let magic_reason = if !overlay_supported {
+                                Some("kernel lacks overlayfs support")
+                            } else if !canonical_target.is_dir() {
+                                Some("target is not a directory")
+                            } else if has_nested_mounts(&mount_points, &canonical_target) {
+                                Some("target has nested mountpoints")
+                            } else {
+                                None
+                            };
+
+                            if let Some(reason) = magic_reason {
+                                plan.auto_decisions.push(AutoDecision {
+                                    module_id: module.id.clone(),
+                                    relative_path: relative_path.clone(),
+                                    target: canonical_target.display().to_string(),
+                                    mode: MountMode::Magic,
+                                    reason: reason.to_string(),
+                                });
+                                magic_subtrees
+                                    .entry(module.id.clone())
+                                    .or_default()
+                                    .push(relative_path);
+                                continue;
+                            }
+
+                            auto_layers.push(AutoLayer {
+                                module_id: module.id.clone(),
+                                relative_path: relative_path.clone(),
+                                target: canonical_target.clone(),
+                                source: module_source.clone(),
+                            });
+                        }
+
+                        overlay_groups.entry(canonical_target).or_default().push((
+                            module_source,
+                            LayerOrigin {
+                                module_id: module.id.clone(),
+                                relative_path,
+                            },
+                        ));
                     }
                 }
             }
         }
     }
 
+    let max_layers = max_direct_layers();
+    let crowded_targets: HashMap<PathBuf, usize> = overlay_groups
+        .iter()
+        .filter(|(_, layers)| layers.len() > max_layers)
+        .map(|(target, layers)| (target.clone(), layers.len()))
+        .collect();
+
+    let mut demoted: HashSet<PathBuf> = HashSet::new();
+    for (target, count) in &crowded_targets {
+        let auto_sources: HashSet<&PathBuf> = auto_layers
+            .iter()
+            .filter(|layer| layer.target == *target)
+            .map(|layer| &layer.source)
+            .collect();
+        demoted.extend(
+            overlay_groups[target]
+                .iter()
+                .map(|(source, _)| source)
+                .take_while(|source| auto_sources.contains(source))
+                .take(count - max_layers)
+                .cloned(),
+        );
+    }
+
+    for layer in auto_layers {
+        let (mode, reason) = match crowded_targets.get(&layer.target) {
+            Some(count) if demoted.contains(&layer.source) => {
+                if let Some(layers) = overlay_groups.get_mut(&layer.target) {
+                    layers.retain(|(source, _)| *source != layer.source);
+                }
+                magic_subtrees
+                    .entry(layer.module_id.clone())
+                    .or_default()
+                    .push(layer.relative_path.clone());
+                (
+                    MountMode::Magic,
+                    format!(
+                        "target has {} layers, above the limit of {}, and this is one of the highest-precedence layers, which magic mount keeps on top",
+                        count, max_layers
+                    ),
+                )
+            }
+            _ => (
+                MountMode::Overlay,
+                "target is a directory without nested mountpoints".to_string(),
+            ),
+        };
+
+        plan.auto_decisions.push(AutoDecision {
+            module_id: layer.module_id,
+            relative_path: layer.relative_path,
+            target: layer.target.display().to_string(),
+            mode,
+            reason,
+        });
+    }
+
+    let rw_targets: HashSet<PathBuf> = config
+        .rw_partitions
+        .iter()
+        .filter_map(|partition| Path::new("/").join(partition).canonicalize().ok())
+        .filter(|target| target.is_dir())
+        .collect();
+    for target in &rw_targets {
+        overlay_groups.entry(target.clone()).or_default();
+    }
+
+    let mut overlay_ids = HashSet::new();
+
     for (target_path, layers) in overlay_groups {
         let target_str = target_path.to_string_lossy().to_string();
 
+        if layers.is_empty() && !rw_targets.contains(&target_path) {
+            continue;
+        }
+
         if !target_path.is_dir() {
+            for (_, origin) in &layers {
+                plan.diagnostics.push(DiagnosticIssue {
+                    level: DiagnosticLevel::Critical,
+                    code: DiagnosticCode::TargetNotDirectory,
+                    context: origin.module_id.clone(),
+                    message: format!(
+                        "Overlay target {} is not a directory; {} is not mounted",
+                        target_str, origin.relative_path
+                    ),
+                    modules: Vec::new(),
+                });
+            }
             continue;
         }
 
+        let (lowerdirs, origins): (Vec<PathBuf>, Vec<LayerOrigin>) = layers.into_iter().unzip();
+
+        overlay_ids.extend(origins.iter().map(|o| o.module_id.clone()));
+
         let partition_name = target_path
             .iter()
             .nth(1)
@@ -286,12 +1205,27 @@ pub fn generate(
         plan.overlay_ops.push(OverlayOperation {
             partition_name,
             target: target_str,
-            lowerdirs: layers,
+            lowerdirs,
+            origins,
         });
     }
 
+    plan.overlay_ops.sort_by(|a, b| a.target.cmp(&b.target));
+
+    plan.module_order = modules.iter().map(|m| m.id.clone()).collect();
+    plan.module_rules = modules
+        .iter()
+        .map(|m| {
+            let rules = effective_rules
+                .remove(&m.id)
+                .unwrap_or_else(|| m.rules.clone());
+            (m.id.clone(), rules)
+        })
+        .collect();
+    plan.conflict_policy = config.conflict_policy.clone();
     plan.overlay_module_ids = overlay_ids.into_iter().collect();
-    plan.magic_module_ids = magic_ids.into_iter().collect();
+    plan.magic_module_ids = magic_subtrees.keys().cloned().collect();
+    plan.magic_subtrees = magic_subtrees;
     plan.overlay_module_ids.sort();
     plan.magic_module_ids.sort();
 
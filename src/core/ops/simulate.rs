@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use super::{
+    diff::{self, PlanComparison},
+    planner::{self, ConflictEntry, DiagnosticIssue},
+};
+use crate::{
+    conf::config::Config,
+    core::inventory::{self, Inventory},
+};
+
+#[derive(Debug, Serialize)]
+pub struct SkippedChange {
+    pub module_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Simulation {
+    pub added_modules: Vec<String>,
+    pub updated_modules: Vec<String>,
+    pub disabled_modules: Vec<String>,
+    pub newly_skipped: Vec<SkippedChange>,
+    pub no_longer_skipped: Vec<String>,
+    pub new_conflicts: Vec<ConflictEntry>,
+    pub resolved_conflicts: Vec<ConflictEntry>,
+    pub new_diagnostics: Vec<DiagnosticIssue>,
+    pub resolved_diagnostics: Vec<DiagnosticIssue>,
+    pub plan: PlanComparison,
+}
+
+fn module_ids(inventory: &Inventory) -> Vec<String> {
+    inventory
+        .modules
+        .iter()
+        .chain(inventory.skipped.iter().map(|s| &s.module))
+        .map(|m| m.id.clone())
+        .collect()
+}
+
+fn same_conflict(a: &ConflictEntry, b: &ConflictEntry) -> bool {
+    a.relative_path == b.relative_path
+        && a.kind == b.kind
+        && a.winner == b.winner
+        && a.contending_modules == b.contending_modules
+}
+
+fn same_diagnostic(a: &DiagnosticIssue, b: &DiagnosticIssue) -> bool {
+    a.code == b.code && a.context == b.context && a.message == b.message
+}
+
+fn only_in<T: Clone>(items: &[T], other: &[T], same: fn(&T, &T) -> bool) -> Vec<T> {
+    items
+        .iter()
+        .filter(|item| !other.iter().any(|o| same(item, o)))
+        .cloned()
+        .collect()
+}
+
+pub fn simulate(config: &Config, add: &[PathBuf], disable: &[String]) -> Result<Simulation> {
+    let baseline = inventory::scan_inventory(&config.moduledir, config)
+        .context("Failed to scan installed modules")?;
+    let simulated = inventory::scan_virtual(&config.moduledir, config, add, disable)
+        .context("Failed to scan simulated module set")?;
+
+    let before = planner::generate(config, &baseline.modules, &config.moduledir)
+        .context("Failed to generate current plan")?;
+    let after = planner::generate(config, &simulated.modules, &config.moduledir)
+        .context("Failed to generate simulated plan")?;
+
+    let before_report = before.analyze();
+    let after_report = after.analyze();
+
+    let baseline_ids = module_ids(&baseline);
+    let (updated_modules, added_modules) = simulated
+        .modules
+        .iter()
+        .chain(simulated.skipped.iter().map(|s| &s.module))
+        .filter(|m| add.contains(&m.source_path))
+        .map(|m| m.id.clone())
+        .partition(|id| baseline_ids.contains(id));
+
+    let newly_skipped = simulated
+        .skipped
+        .iter()
+        .filter(|s| !baseline.skipped.iter().any(|b| b.module.id == s.module.id))
+        .map(|s| SkippedChange {
+            module_id: s.module.id.clone(),
+            reason: s.reason.clone(),
+        })
+        .collect();
+
+    let no_longer_skipped = baseline
+        .skipped
+        .iter()
+        .filter(|b| simulated.modules.iter().any(|m| m.id == b.module.id))
+        .map(|b| b.module.id.clone())
+        .collect();
+
+    Ok(Simulation {
+        added_modules,
+        updated_modules,
+        disabled_modules: disable.to_vec(),
+        newly_skipped,
+        no_longer_skipped,
+        new_conflicts: only_in(
+            &after_report.conflicts,
+            &before_report.conflicts,
+            same_conflict,
+        ),
+        resolved_conflicts: only_in(
+            &before_report.conflicts,
+            &after_report.conflicts,
+            same_conflict,
+        ),
+        new_diagnostics: only_in(
+            &after_report.diagnostics,
+            &before_report.diagnostics,
+            same_diagnostic,
+        ),
+        resolved_diagnostics: only_in(
+            &before_report.diagnostics,
+            &after_report.diagnostics,
+            same_diagnostic,
+        ),
+        plan: diff::compare(&before, &after),
+    })
+}
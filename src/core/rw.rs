@@ -0,0 +1,445 @@
+use std::{
+    fs,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::fs::statvfs;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{conf::config::Config, defs, utils};
+
+const MIN_FREE_BYTES: u64 = 64 * 1024 * 1024;
+const RESET_MARKER: &str = ".reset_pending";
+
+#[derive(Debug, Serialize)]
+pub struct RwUsage {
+    pub partition: String,
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub files: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_bytes: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RwChange {
+    pub path: String,
+    pub kind: String,
+}
+
+pub fn partition_dir(partition: &str) -> PathBuf {
+    Path::new(defs::SYSTEM_RW_DIR).join(partition)
+}
+
+fn target_relative(partition: &str, target: &str) -> String {
+    let target = target.trim_end_matches('/');
+    [format!("/{partition}"), format!("/system/{partition}")]
+        .iter()
+        .find_map(|root| target.strip_prefix(root.as_str()))
+        .unwrap_or(target)
+        .trim_matches('/')
+        .to_string()
+}
+
+fn target_dirs(partition: &str, target: &str) -> (PathBuf, PathBuf) {
+    let base = partition_dir(partition);
+    let relative = target_relative(partition, target);
+    let work_name = format!("/{relative}")
+        .replace('%', "%25")
+        .replace('/', "%2F");
+
+    (
+        base.join("upperdir").join(&relative),
+        base.join("workdir").join(work_name),
+    )
+}
+
+fn validate_partition(partition: &str) -> Result<()> {
+    if partition.is_empty() || partition.contains('/') || partition.starts_with('.') {
+        bail!("Invalid partition name: '{partition}'");
+    }
+    Ok(())
+}
+
+fn free_bytes(path: &Path) -> Option<u64> {
+    statvfs(path)
+        .ok()
+        .map(|st| st.f_bavail.saturating_mul(st.f_frsize))
+}
+
+fn dir_usage(path: &Path) -> (u64, u64) {
+    WalkDir::new(path)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .fold((0, 0), |(bytes, files), metadata| {
+            let is_file = !metadata.is_dir();
+            (bytes + metadata.blocks() * 512, files + u64::from(is_file))
+        })
+}
+
+fn label_like(path: &Path, reference: &Path) {
+    match utils::lgetfilecon(reference) {
+        Ok(con) => {
+            if let Err(e) = utils::lsetfilecon(path, &con) {
+                log::warn!("Failed to label {}: {:#}", path.display(), e);
+            }
+        }
+        Err(e) => log::debug!("No SELinux context for {}: {:#}", reference.display(), e),
+    }
+}
+
+pub fn prepare(config: &Config, partition: &str, target: &str) -> Option<(PathBuf, PathBuf)> {
+    let marker = partition_dir(partition).join(RESET_MARKER);
+    if marker.exists() {
+        match clear(partition) {
+            Ok(()) => {
+                log::info!("Reset upperdir for {} as scheduled by export", partition);
+                let _ = fs::remove_file(&marker);
+            }
+            Err(e) => log::warn!("Failed to reset upperdir for {}: {:#}", partition, e),
+        }
+    }
+
+    if !config.rw_partitions.iter().any(|p| p == partition) {
+        return None;
+    }
+
+    let (upper, work) = target_dirs(partition, target);
+    for dir in [&upper, &work] {
+        if let Err(e) = utils::ensure_dir_exists(dir) {
+            log::warn!("Failed to create {}: {:#}", dir.display(), e);
+            return None;
+        }
+    }
+
+    if let Some(free) = free_bytes(&upper)
+        && free < MIN_FREE_BYTES
+    {
+        log::warn!(
+            "Only {} MiB free for {} upperdir, mounting it read-only.",
+            free / 1024 / 1024,
+            partition
+        );
+        return None;
+    }
+
+    if let Some(limit_mb) = config.rw_max_mb {
+        let (bytes, _) = dir_usage(&partition_dir(partition).join("upperdir"));
+        if bytes > limit_mb * 1024 * 1024 {
+            log::warn!(
+                "{} upperdir uses {} MiB, above rw_max_mb = {}, mounting it read-only.",
+                partition,
+                bytes / 1024 / 1024,
+                limit_mb
+            );
+            return None;
+        }
+    }
+
+    let target = Path::new(target);
+    label_like(&upper, target);
+    label_like(&work, target);
+
+    Some((upper, work))
+}
+
+pub fn warn_unmanaged(config: &Config) {
+    let Ok(entries) = fs::read_dir(defs::SYSTEM_RW_DIR) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let partition = entry.file_name().to_string_lossy().to_string();
+        if config.rw_partitions.contains(&partition) {
+            continue;
+        }
+
+        let (_, files) = dir_usage(&entry.path().join("upperdir"));
+        if files > 0 {
+            log::warn!(
+                "{} has an upperdir with {} changed files but is not in rw_partitions, mounting it read-only. Run `hybrid-mount rw enable {}` to keep it writable.",
+                partition,
+                files,
+                partition
+            );
+        }
+    }
+}
+
+pub fn enable(config: &mut Config, partition: &str) -> Result<()> {
+    validate_partition(partition)?;
+
+    let root = Path::new("/").join(partition);
+    if !root.is_dir() {
+        bail!("Partition {} does not exist", root.display());
+    }
+
+    let base = partition_dir(partition);
+    let upper = base.join("upperdir");
+    utils::ensure_dir_exists(&upper)?;
+    utils::ensure_dir_exists(base.join("workdir"))?;
+    label_like(&upper, &root);
+
+    if !config.rw_partitions.iter().any(|p| p == partition) {
+        config.rw_partitions.push(partition.to_string());
+    }
+
+    Ok(())
+}
+
+pub fn disable(config: &mut Config, partition: &str) -> Result<()> {
+    validate_partition(partition)?;
+
+    config.rw_partitions.retain(|p| p != partition);
+
+    Ok(())
+}
+
+fn in_use(partition: &str) -> bool {
+    let base = partition_dir(partition);
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    mountinfo.contains(base.to_string_lossy().as_ref())
+}
+
+fn clear(partition: &str) -> Result<()> {
+    let base = partition_dir(partition);
+    for name in ["upperdir", "workdir"] {
+        let dir = base.join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        utils::ensure_dir_exists(&dir)?;
+    }
+    label_like(&base.join("upperdir"), &Path::new("/").join(partition));
+
+    Ok(())
+}
+
+pub fn reset(partition: &str) -> Result<()> {
+    validate_partition(partition)?;
+
+    if in_use(partition) {
+        bail!(
+            "{} is in use by a mounted overlay; disable it and reboot before resetting",
+            partition_dir(partition).display()
+        );
+    }
+
+    clear(partition)
+}
+
+pub fn reset_or_schedule(partition: &str) -> Result<bool> {
+    validate_partition(partition)?;
+
+    if !in_use(partition) {
+        clear(partition)?;
+        return Ok(false);
+    }
+
+    fs::write(partition_dir(partition).join(RESET_MARKER), "")
+        .with_context(|| format!("Failed to schedule reset of {}", partition))?;
+
+    Ok(true)
+}
+
+pub fn usage(config: &Config) -> Vec<RwUsage> {
+    let mut partitions = config.rw_partitions.clone();
+    if let Ok(entries) = fs::read_dir(defs::SYSTEM_RW_DIR) {
+        partitions.extend(
+            entries
+                .flatten()
+                .filter(|e| e.path().is_dir())
+                .map(|e| e.file_name().to_string_lossy().to_string()),
+        );
+    }
+    partitions.sort();
+    partitions.dedup();
+
+    partitions
+        .into_iter()
+        .map(|partition| {
+            let path = partition_dir(&partition);
+            let (bytes, files) = dir_usage(&path.join("upperdir"));
+            RwUsage {
+                enabled: config.rw_partitions.contains(&partition),
+                free_bytes: free_bytes(&path),
+                limit_bytes: config.rw_max_mb.map(|mb| mb * 1024 * 1024),
+                partition,
+                path,
+                bytes,
+                files,
+            }
+        })
+        .collect()
+}
+
+pub fn changes(partition: &str) -> Result<Vec<RwChange>> {
+    validate_partition(partition)?;
+
+    let upper = partition_dir(partition).join("upperdir");
+    if !upper.is_dir() {
+        bail!("No upperdir for partition {}", partition);
+    }
+
+    let mut changes = Vec::new();
+    for entry in WalkDir::new(&upper)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+    {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let file_type = metadata.file_type();
+
+        let kind = if file_type.is_char_device() && metadata.rdev() == 0 {
+            "deleted"
+        } else if file_type.is_dir() {
+            if !utils::is_overlay_opaque(path) {
+                continue;
+            }
+            "replaced"
+        } else {
+            "changed"
+        };
+
+        let relative = path.strip_prefix(&upper).unwrap_or(path);
+        changes.push(RwChange {
+            path: Path::new("/")
+                .join(partition)
+                .join(relative)
+                .display()
+                .to_string(),
+            kind: kind.to_string(),
+        });
+    }
+
+    Ok(changes)
+}
+
+pub fn export(
+    config: &Config,
+    module_id: &str,
+    partition: Option<&str>,
+) -> Result<(PathBuf, Vec<String>)> {
+    utils::validate_module_id(module_id)?;
+
+    let dst = config.moduledir.join(module_id);
+    if dst.exists() {
+        bail!("Module {} already exists at {}", module_id, dst.display());
+    }
+
+    let partitions: Vec<String> = match partition {
+        Some(partition) => {
+            validate_partition(partition)?;
+            vec![partition.to_string()]
+        }
+        None => usage(config)
+            .into_iter()
+            .filter(|u| u.files > 0)
+            .map(|u| u.partition)
+            .collect(),
+    };
+
+    if partitions.is_empty() {
+        bail!("No read-write changes to export");
+    }
+
+    let tmp = config.moduledir.join(format!(".tmp_{module_id}"));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+
+    if let Err(e) = build_export(&tmp, module_id, &partitions) {
+        let _ = fs::remove_dir_all(&tmp);
+        return Err(e);
+    }
+
+    fs::rename(&tmp, &dst).with_context(|| format!("Failed to create {}", dst.display()))?;
+
+    Ok((dst, partitions))
+}
+
+fn build_export(tmp: &Path, module_id: &str, partitions: &[String]) -> Result<()> {
+    for partition in partitions {
+        let upper = partition_dir(partition).join("upperdir");
+        if !upper.is_dir() {
+            bail!("No upperdir for partition {}", partition);
+        }
+
+        let dst = tmp.join(partition);
+        utils::sync_dir(&upper, &dst, false)?;
+        normalize_export(&dst, &Path::new("/").join(partition))?;
+    }
+
+    let prop = format!(
+        "id={module_id}\nname={module_id}\nversion=v1\nversionCode=1\nauthor=hybrid-mount\ndescription=Exported from read-write changes of {}\n",
+        partitions.join(", ")
+    );
+    fs::write(tmp.join("module.prop"), prop)?;
+    fs::write(tmp.join("hybrid_rules.json"), "{}\n")?;
+
+    Ok(())
+}
+
+fn normalize_export(root: &Path, live_root: &Path) -> Result<()> {
+    let entries: Vec<PathBuf> = WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .flatten()
+        .map(|e| e.into_path())
+        .collect();
+
+    let mut replaced: Vec<PathBuf> = Vec::new();
+    for path in entries {
+        if replaced.iter().any(|r| path.starts_with(r)) {
+            continue;
+        }
+
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let live = live_root.join(relative);
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+
+        if metadata.is_dir() && utils::has_overlay_xattr(&path, "redirect") {
+            fs::remove_dir_all(&path)?;
+            utils::sync_dir(&live, &path, false).with_context(|| {
+                format!(
+                    "Renamed directory {} needs the mounted overlay",
+                    live.display()
+                )
+            })?;
+            fs::write(path.join(defs::REPLACE_DIR_FILE_NAME), "")?;
+            replaced.push(path);
+            continue;
+        }
+
+        if metadata.is_file() && utils::has_overlay_xattr(&path, "metacopy") {
+            utils::reflink_or_copy(&live, &path).with_context(|| {
+                format!("Metacopy file {} needs the mounted overlay", live.display())
+            })?;
+        }
+
+        if metadata.is_dir() && utils::is_overlay_opaque(&path) {
+            fs::write(path.join(defs::REPLACE_DIR_FILE_NAME), "")?;
+        }
+
+        if !metadata.is_symlink() {
+            utils::strip_overlay_xattrs(&path)?;
+        }
+    }
+
+    Ok(())
+}
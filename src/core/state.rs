@@ -7,7 +7,20 @@ use std::{
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::{defs, utils::fs::xattr};
+use crate::{
+    defs,
+    sys::capabilities::{self, Capabilities},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetOutcome {
+    pub target: String,
+    pub partition: String,
+    pub mechanism: String,
+    pub modules: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct RuntimeState {
@@ -21,6 +34,10 @@ pub struct RuntimeState {
     pub active_mounts: Vec<String>,
     #[serde(default)]
     pub tmpfs_xattr_supported: bool,
+    #[serde(default)]
+    pub target_outcomes: Vec<TargetOutcome>,
+    #[serde(default)]
+    pub capabilities: Capabilities,
 }
 
 impl RuntimeState {
@@ -31,6 +48,7 @@ impl RuntimeState {
         overlay_modules: Vec<String>,
         magic_modules: Vec<String>,
         active_mounts: Vec<String>,
+        target_outcomes: Vec<TargetOutcome>,
     ) -> Self {
         let start = SystemTime::now();
 
@@ -41,7 +59,7 @@ impl RuntimeState {
 
         let pid = std::process::id();
 
-        let tmpfs_xattr_supported = xattr::is_overlay_xattr_supported().unwrap_or(false);
+        let capabilities = capabilities::get().clone();
 
         Self {
             timestamp,
@@ -51,7 +69,9 @@ impl RuntimeState {
             overlay_modules,
             magic_modules,
             active_mounts,
-            tmpfs_xattr_supported,
+            tmpfs_xattr_supported: capabilities.tmpfs_xattr_supported(),
+            capabilities,
+            target_outcomes,
         }
     }
 
@@ -17,12 +17,18 @@ use crate::mount::umount_mgr::send_umountable;
 use crate::{
     defs,
     mount::overlayfs::utils as overlay_utils,
-    sys::{mount::is_mounted, nuke},
+    sys::{
+        capabilities::{self, Capabilities},
+        mount::is_mounted,
+        nuke,
+    },
     utils::{self, ensure_dir_exists, lsetfilecon},
 };
 
 const DEFAULT_SELINUX_CONTEXT: &str = "u:object_r:system_file:s0";
 
+pub const EROFS_STAGING_MODE: &str = "erofs_staging";
+
 pub struct StorageHandle {
     pub mount_point: PathBuf,
     pub mode: String,
@@ -32,7 +38,7 @@ pub struct StorageHandle {
 
 impl StorageHandle {
     pub fn commit(&mut self, disable_umount: bool) -> Result<()> {
-        if self.mode == "erofs_staging" {
+        if self.mode == EROFS_STAGING_MODE {
             let image_path = self
                 .backing_image
                 .as_ref()
@@ -150,7 +156,7 @@ pub fn setup(
         }
     };
 
-    if use_erofs && is_erofs_supported() {
+    if use_erofs && capabilities::get().erofs {
         let erofs_path = img_path.with_extension("erofs");
         let staging_dir = Path::new(defs::RUN_DIR).join("erofs_staging");
 
@@ -169,7 +175,7 @@ pub fn setup(
 
         return Ok(StorageHandle {
             mount_point: staging_dir,
-            mode: "erofs_staging".to_string(),
+            mode: EROFS_STAGING_MODE.to_string(),
             backing_image: Some(erofs_path),
             final_target: Some(mnt_base.to_path_buf()),
         });
@@ -197,10 +203,20 @@ pub fn setup(
     Ok(handle)
 }
 
+pub fn predict_mode(force_ext4: bool, use_erofs: bool, caps: &Capabilities) -> &'static str {
+    if use_erofs && caps.erofs {
+        EROFS_STAGING_MODE
+    } else if !force_ext4 && caps.tmpfs_xattr_supported() {
+        "tmpfs"
+    } else {
+        "ext4"
+    }
+}
+
 fn try_setup_tmpfs(target: &Path, mount_source: &str) -> Result<bool> {
     if crate::sys::mount::mount_tmpfs(target, mount_source).is_ok() {
-        if utils::is_overlay_xattr_supported().unwrap_or(false) {
-            log::info!("Tmpfs mounted and supports xattrs (CONFIG_TMPFS_XATTR=y).");
+        if utils::is_trusted_xattr_supported(target) {
+            log::info!("Tmpfs mounted and supports trusted xattrs.");
             return Ok(true);
         } else {
             let _ = umount(target, UnmountFlags::DETACH);
@@ -268,12 +284,6 @@ fn setup_ext4_image(target: &Path, img_path: &Path, moduledir: &Path) -> Result<
     })
 }
 
-fn is_erofs_supported() -> bool {
-    fs::read_to_string("/proc/filesystems")
-        .map(|content| content.contains("erofs"))
-        .unwrap_or(false)
-}
-
 fn create_erofs_image(src_dir: &Path, image_path: &Path) -> Result<()> {
     let mkfs_bin = Path::new(defs::MKFS_EROFS_PATH);
     let cmd_name = if mkfs_bin.exists() {
@@ -66,3 +66,4 @@ pub const IGNORE_UNOUNT_PARTITIONS: &[&str] = &[
 
 pub const REPLACE_DIR_FILE_NAME: &str = ".replace";
 pub const REPLACE_DIR_XATTR: &str = "trusted.overlay.opaque";
+pub const CONFLICT_POLICY_STOCK: &str = "stock";
@@ -16,6 +16,7 @@ use conf::{
     config::Config,
 };
 use mimalloc::MiMalloc;
+use sys::capabilities;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -78,8 +79,23 @@ fn main() -> Result<()> {
                 cli_handlers::handle_save_module_rules(module, payload)?
             }
             Commands::Modules => cli_handlers::handle_modules(&cli)?,
-            Commands::Conflicts => cli_handlers::handle_conflicts(&cli)?,
-            Commands::Diagnostics => cli_handlers::handle_diagnostics(&cli)?,
+            Commands::Conflicts { module, report } => {
+                cli_handlers::handle_conflicts(&cli, module.as_deref(), report)?
+            }
+            Commands::Diagnostics { module, report } => {
+                cli_handlers::handle_diagnostics(&cli, module.as_deref(), report)?
+            }
+            Commands::Lint { target, report } => cli_handlers::handle_lint(&cli, target, report)?,
+            Commands::Impact { target, summary } => {
+                cli_handlers::handle_impact(&cli, target.as_deref(), *summary)?
+            }
+            Commands::Capabilities => cli_handlers::handle_capabilities(&cli)?,
+            Commands::Plan { diff } => cli_handlers::handle_plan(&cli, *diff)?,
+            Commands::Explain { path } => cli_handlers::handle_explain(&cli, path)?,
+            Commands::Simulate { add, disable } => {
+                cli_handlers::handle_simulate(&cli, add, disable)?
+            }
+            Commands::Rw { action } => cli_handlers::handle_rw(&cli, action)?,
         }
 
         return Ok(());
@@ -105,6 +121,8 @@ fn main() -> Result<()> {
 
     utils::check_ksu();
 
+    capabilities::init();
+
     if config.disable_umount {
         log::warn!("!! Umount is DISABLED via config.");
     }
@@ -3,7 +3,7 @@
 mod utils;
 
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     fs,
     path::{Path, PathBuf},
     sync::atomic::AtomicU32,
@@ -18,6 +18,7 @@ use rustix::mount::{
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::mount::umount_mgr::send_umountable;
 use crate::{
+    conf::config::ModuleRules,
     mount::{
         magic_mount::utils::{clone_symlink, collect_module_files, mount_mirror},
         node::{Node, NodeFileType},
@@ -300,19 +301,28 @@ impl MagicMount {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn magic_mount<P>(
     tmp_path: P,
     module_dir: &Path,
     mount_source: &str,
     extra_partitions: &[String],
-    need_id: HashSet<String>,
+    ordered_ids: &[String],
+    module_rules: &HashMap<String, ModuleRules>,
+    magic_subtrees: &HashMap<String, Vec<String>>,
     #[cfg(any(target_os = "linux", target_os = "android"))] umount: bool,
     #[cfg(not(any(target_os = "linux", target_os = "android")))] _umount: bool,
 ) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    if let Some(root) = collect_module_files(module_dir, extra_partitions, need_id)? {
+    if let Some(root) = collect_module_files(
+        module_dir,
+        extra_partitions,
+        ordered_ids,
+        module_rules,
+        magic_subtrees,
+    )? {
         log::debug!("collected: {root:?}");
         let tmp_root = tmp_path.as_ref();
         let tmp_dir = tmp_root.join("workdir");
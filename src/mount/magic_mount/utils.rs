@@ -1,7 +1,7 @@
 // Copyright 2026 https://github.com/Tools-cx-app/meta-magic_mount
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs::{self, DirEntry, Metadata, create_dir, create_dir_all, read_link},
     os::unix::fs::{MetadataExt, symlink},
     path::{Path, PathBuf},
@@ -14,6 +14,8 @@ use rustix::{
 };
 
 use crate::{
+    conf::config::{ModuleRules, MountMode},
+    core::inventory::model::ModuleProp,
     defs::{DISABLE_FILE_NAME, REMOVE_FILE_NAME, SKIP_MOUNT_FILE_NAME},
     mount::node::Node,
     utils::{lgetfilecon, lsetfilecon, validate_module_id},
@@ -108,7 +110,9 @@ where
 pub fn collect_module_files(
     module_dir: &Path,
     extra_partitions: &[String],
-    need_id: HashSet<String>,
+    ordered_ids: &[String],
+    module_rules: &HashMap<String, ModuleRules>,
+    magic_subtrees: &HashMap<String, Vec<String>>,
 ) -> Result<Option<Node>> {
     let mut root = Node::new_root("");
     let mut system = Node::new_root("system");
@@ -117,36 +121,31 @@ pub fn collect_module_files(
 
     log::debug!("begin collect module files: {}", module_root.display());
 
-    for entry in module_root.read_dir()?.flatten() {
-        if !entry.file_type()?.is_dir() {
+    for id in ordered_ids {
+        let module_path = module_root.join(id);
+        if !module_path.is_dir() {
+            log::debug!("module {id} not found in {}", module_root.display());
             continue;
         }
 
-        let id = entry.file_name().to_str().unwrap().to_string();
         log::debug!("processing new module: {id}");
 
-        if !need_id.contains(&id) {
-            log::debug!("module {id} was blocked.");
-            continue;
-        }
-
-        let prop = entry.path().join("module.prop");
+        let prop = module_path.join("module.prop");
         if !prop.exists() {
             log::debug!("skipped module {id}, because not found module.prop");
             continue;
         }
-        let string = fs::read_to_string(prop)?;
-        for line in string.lines() {
-            if line.starts_with("id")
-                && let Some((_, value)) = line.split_once('=')
-            {
-                validate_module_id(value)?;
-            }
+        let prop = ModuleProp::load(&prop)?;
+        if !prop.id.is_empty() {
+            validate_module_id(&prop.id)?;
+        }
+        if prop.id_mismatch(id) {
+            log::warn!("module {id} declares mismatched id '{}'", prop.id);
         }
 
-        if entry.path().join(DISABLE_FILE_NAME).exists()
-            || entry.path().join(REMOVE_FILE_NAME).exists()
-            || entry.path().join(SKIP_MOUNT_FILE_NAME).exists()
+        if module_path.join(DISABLE_FILE_NAME).exists()
+            || module_path.join(REMOVE_FILE_NAME).exists()
+            || module_path.join(SKIP_MOUNT_FILE_NAME).exists()
         {
             log::debug!("skipped module {id}, due to disable/remove/skip_mount");
             continue;
@@ -158,7 +157,7 @@ pub fn collect_module_files(
         partitions.extend(extra_partitions.iter().cloned());
 
         for p in &partitions {
-            if entry.path().join(p).is_dir() {
+            if module_path.join(p).is_dir() {
                 modified = true;
                 break;
             }
@@ -169,14 +168,34 @@ pub fn collect_module_files(
             continue;
         }
 
-        log::debug!("collecting {}", entry.path().display());
+        log::debug!("collecting {}", module_path.display());
+
+        let rules = module_rules.get(id);
+        let subtrees = magic_subtrees.get(id);
+        let filter = |relative: &Path| {
+            let selected = subtrees.is_none_or(|subtrees| {
+                subtrees.iter().any(|s| {
+                    let s = Path::new(s);
+                    relative.starts_with(s) || s.starts_with(relative)
+                })
+            });
+
+            selected
+                && rules.is_none_or(|r| {
+                    !matches!(r.get_mode(&relative.to_string_lossy()), MountMode::Ignore)
+                })
+        };
 
         for p in partitions {
-            if !entry.path().join(&p).exists() {
+            if !module_path.join(&p).exists() || !filter(Path::new(&p)) {
                 continue;
             }
 
-            has_file.insert(system.collect_module_files(entry.path().join(&p))?);
+            has_file.insert(system.collect_module_files(
+                module_path.join(&p),
+                Path::new(&p),
+                &filter,
+            )?);
         }
     }
 
@@ -57,14 +57,25 @@ impl fmt::Display for Node {
 }
 
 impl Node {
-    pub fn collect_module_files<P>(&mut self, module_dir: P) -> Result<bool>
+    pub fn collect_module_files<P, F>(
+        &mut self,
+        module_dir: P,
+        relative: &Path,
+        filter: &F,
+    ) -> Result<bool>
     where
         P: AsRef<Path>,
+        F: Fn(&Path) -> bool,
     {
         let dir = module_dir.as_ref();
         let mut has_file = false;
         for entry in dir.read_dir()?.flatten() {
             let name = entry.file_name().to_string_lossy().to_string();
+            let entry_relative = relative.join(&name);
+
+            if !filter(&entry_relative) {
+                continue;
+            }
 
             let node = match self.children.entry(name.clone()) {
                 Entry::Occupied(o) => Some(o.into_mut()),
@@ -73,7 +84,8 @@ impl Node {
 
             if let Some(node) = node {
                 has_file |= if node.file_type == NodeFileType::Directory {
-                    node.collect_module_files(dir.join(&node.name))? || node.replace
+                    node.collect_module_files(dir.join(&node.name), &entry_relative, filter)?
+                        || node.replace
                 } else {
                     true
                 }
@@ -83,7 +95,7 @@ impl Node {
         Ok(has_file)
     }
 
-    fn dir_is_replace<P>(path: P) -> bool
+    pub fn dir_is_replace<P>(path: P) -> bool
     where
         P: AsRef<Path>,
     {
@@ -2,6 +2,8 @@
 
 use std::{
     ffi::CString,
+    fs::{read_dir, remove_dir},
+    ops::Range,
     os::fd::AsFd,
     path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
@@ -11,19 +13,101 @@ use anyhow::{Context, Result, bail};
 use procfs::process::Process;
 use rustix::{
     fs::CWD,
-    mount::{MountFlags, MoveMountFlags, mount, move_mount},
+    mount::{MountFlags, MoveMountFlags, UnmountFlags, mount, move_mount, unmount},
 };
 
 use crate::{
+    conf::config::OverlayOptions,
     defs,
     mount::{
         overlayfs::utils::{fs, umount_dir},
         umount_mgr::send_umountable,
     },
+    sys::{capabilities, mount::is_mounted},
     utils::ensure_dir_exists,
 };
 
-const MAX_LAYERS: usize = 64;
+pub const MAX_LAYERS: usize = 64;
+pub const OVL_MAX_STACK: usize = 500;
+
+pub fn max_direct_layers() -> usize {
+    let caps = capabilities::get();
+    let ceiling = if caps.fsopen && caps.lowerdir_append {
+        OVL_MAX_STACK
+    } else {
+        MAX_LAYERS
+    };
+
+    caps.max_layers.unwrap_or(ceiling).min(ceiling)
+}
+
+fn staging_root() -> PathBuf {
+    Path::new(defs::RUN_DIR).join("staging")
+}
+
+pub fn cleanup_staging() {
+    let root = staging_root();
+    let Ok(entries) = read_dir(&root) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if is_mounted(&path) {
+            let _ = unmount(&path, UnmountFlags::DETACH);
+        }
+        if let Err(e) = remove_dir(&path) {
+            log::warn!(
+                "Failed to remove stale staging dir {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+// Overlayfs keeps private clones of its layers, so staging mounts can be
+// detached once the final overlay is up and leave nothing behind to umount.
+fn release_staging(staging_mounts: &[PathBuf]) {
+    for staging_dir in staging_mounts.iter().rev() {
+        if let Err(e) = unmount(staging_dir, UnmountFlags::DETACH) {
+            log::warn!("Failed to detach staging {}: {}", staging_dir.display(), e);
+        }
+        let _ = remove_dir(staging_dir);
+    }
+}
+
+fn balanced_chunks(len: usize, max_layers: usize) -> Vec<Range<usize>> {
+    let count = len.div_ceil(max_layers.max(2));
+    let (base, extra) = (len / count, len % count);
+
+    let mut start = 0;
+    (0..count)
+        .map(|i| {
+            let end = start + base + usize::from(i < extra);
+            let range = start..end;
+            start = end;
+            range
+        })
+        .collect()
+}
+
+fn legacy_mount_flags(options: &OverlayOptions) -> MountFlags {
+    let mut flags = MountFlags::empty();
+    if options.nosuid == Some(true) {
+        flags |= MountFlags::NOSUID;
+    }
+    if options.nodev == Some(true) {
+        flags |= MountFlags::NODEV;
+    }
+    if options.noexec == Some(true) {
+        flags |= MountFlags::NOEXEC;
+    }
+    if options.noatime == Some(true) {
+        flags |= MountFlags::NOATIME;
+    }
+    flags
+}
 
 fn mount_overlay_core(
     lower_dirs: &[String],
@@ -31,14 +115,16 @@ fn mount_overlay_core(
     workdir: Option<&Path>,
     dest: &Path,
     mount_source: &str,
+    options: &OverlayOptions,
 ) -> Result<()> {
-    let lowerdir_config = lower_dirs.join(":");
+    let append_layers = capabilities::get().lowerdir_append;
 
     log::debug!(
-        "core mount overlayfs on {:?}, layers={}, source={}",
+        "core mount overlayfs on {:?}, layers={}, source={}, lowerdir+={}",
         dest,
         lower_dirs.len(),
-        mount_source
+        mount_source,
+        append_layers
     );
 
     let upperdir_s = upperdir
@@ -51,14 +137,17 @@ fn mount_overlay_core(
     if let Err(e) = fs(
         upperdir_s.clone(),
         workdir_s.clone(),
-        lowerdir_config.clone(),
+        lower_dirs,
+        append_layers,
         mount_source,
         dest,
+        options,
     ) {
         log::warn!("fsopen mount failed: {:#}, fallback to mount", e);
-        let safe_lower = lowerdir_config.replace(',', "\\,");
+        let safe_lower = lower_dirs.join(":").replace(',', "\\,");
         let mut data = format!("lowerdir={safe_lower}");
 
+        let has_upper = upperdir_s.is_some() && workdir_s.is_some();
         if let (Some(upperdir), Some(workdir)) = (upperdir_s, workdir_s) {
             data = format!(
                 "{data},upperdir={},workdir={}",
@@ -66,13 +155,30 @@ fn mount_overlay_core(
                 workdir.replace(',', "\\,")
             );
         }
+        for (key, value) in options.fs_params() {
+            if key == "volatile" && !has_upper {
+                continue;
+            }
+            match value {
+                Some(value) => data = format!("{data},{key}={value}"),
+                None => data = format!("{data},{key}"),
+            }
+        }
         mount(
             mount_source,
             dest,
             "overlay",
-            MountFlags::empty(),
-            Some(CString::new(data)?.as_c_str()),
-        )?;
+            legacy_mount_flags(options),
+            Some(CString::new(data.clone())?.as_c_str()),
+        )
+        .map_err(|legacy| {
+            anyhow::anyhow!(
+                "overlay mount rejected ({}) with options '{}'; fsopen attempt: {:#}",
+                legacy,
+                data,
+                e
+            )
+        })?;
     }
     Ok(())
 }
@@ -84,35 +190,111 @@ pub fn mount_overlayfs(
     workdir: Option<PathBuf>,
     dest: impl AsRef<Path>,
     mount_source: &str,
+    options: &OverlayOptions,
 ) -> Result<()> {
     let mut current_layers: Vec<String> = lower_dirs.to_vec();
     current_layers.push(lowest.to_string());
 
-    while current_layers.len() > MAX_LAYERS {
-        let split_idx = current_layers.len().saturating_sub(MAX_LAYERS - 1);
-        let bottom_chunk: Vec<String> = current_layers.drain(split_idx..).collect();
+    let max_layers = max_direct_layers();
+    let mut staging_mounts = Vec::new();
+    let mut result = stack_layers(
+        current_layers.clone(),
+        upperdir.as_deref(),
+        workdir.as_deref(),
+        dest.as_ref(),
+        mount_source,
+        options,
+        max_layers,
+        &mut staging_mounts,
+    );
 
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_nanos();
-        let staging_dir = Path::new(defs::RUN_DIR).join(format!("staging_{}", timestamp));
+    if let Err(e) = &result
+        && max_layers > MAX_LAYERS
+        && current_layers.len() > MAX_LAYERS
+    {
+        log::warn!(
+            "Overlay with up to {} direct layers failed: {:#}, restacking at {}",
+            max_layers,
+            e,
+            MAX_LAYERS
+        );
+        release_staging(&staging_mounts);
+        staging_mounts.clear();
+        result = stack_layers(
+            current_layers,
+            upperdir.as_deref(),
+            workdir.as_deref(),
+            dest.as_ref(),
+            mount_source,
+            options,
+            MAX_LAYERS,
+            &mut staging_mounts,
+        );
+    }
 
-        ensure_dir_exists(&staging_dir)?;
+    release_staging(&staging_mounts);
 
-        mount_overlay_core(&bottom_chunk, None, None, &staging_dir, mount_source)?;
+    result
+}
+
+fn stack_rounds(
+    mut current_layers: Vec<String>,
+    max_layers: usize,
+    mut mount_stage: impl FnMut(&[String]) -> Result<String>,
+) -> Result<Vec<String>> {
+    while current_layers.len() > max_layers {
+        let mut next_layers = Vec::new();
 
-        let _ = send_umountable(&staging_dir);
+        for range in balanced_chunks(current_layers.len(), max_layers) {
+            let chunk = &current_layers[range];
+            if chunk.len() == 1 {
+                next_layers.push(chunk[0].clone());
+                continue;
+            }
 
-        current_layers.push(staging_dir.to_string_lossy().to_string());
+            next_layers.push(mount_stage(chunk)?);
+        }
+
+        current_layers = next_layers;
     }
 
+    Ok(current_layers)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn stack_layers(
+    current_layers: Vec<String>,
+    upperdir: Option<&Path>,
+    workdir: Option<&Path>,
+    dest: &Path,
+    mount_source: &str,
+    options: &OverlayOptions,
+    max_layers: usize,
+    staging_mounts: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    let current_layers = stack_rounds(current_layers, max_layers, |chunk| {
+        let staging_dir = staging_root().join(format!("{}_{}", timestamp, staging_mounts.len()));
+
+        ensure_dir_exists(&staging_dir)?;
+
+        mount_overlay_core(chunk, None, None, &staging_dir, mount_source, options)?;
+
+        staging_mounts.push(staging_dir.clone());
+        Ok(staging_dir.to_string_lossy().to_string())
+    })?;
+
     mount_overlay_core(
         &current_layers,
-        upperdir.as_deref(),
-        workdir.as_deref(),
-        dest.as_ref(),
+        upperdir,
+        workdir,
+        dest,
         mount_source,
+        options,
     )
 }
 
@@ -158,6 +340,7 @@ fn mount_overlay_child(
     module_roots: &Vec<String>,
     stock_root: &String,
     mount_source: &str,
+    options: &OverlayOptions,
 ) -> Result<()> {
     if !module_roots
         .iter()
@@ -188,6 +371,7 @@ fn mount_overlay_child(
         None,
         mount_point,
         mount_source,
+        options,
     ) {
         log::warn!("failed: {:#}, fallback to bind mount", e);
         bind_mount(stock_root, mount_point)?;
@@ -202,6 +386,7 @@ pub fn mount_overlay(
     workdir: Option<PathBuf>,
     upperdir: Option<PathBuf>,
     mount_source: &str,
+    options: &OverlayOptions,
 ) -> Result<()> {
     log::info!("mount overlay for {}", root);
     std::env::set_current_dir(root).with_context(|| format!("failed to chdir to {root}"))?;
@@ -221,8 +406,16 @@ pub fn mount_overlay(
     mount_seq.sort();
     mount_seq.dedup();
 
-    mount_overlayfs(module_roots, root, upperdir, workdir, root, mount_source)
-        .with_context(|| "mount overlayfs for root failed")?;
+    mount_overlayfs(
+        module_roots,
+        root,
+        upperdir,
+        workdir,
+        root,
+        mount_source,
+        options,
+    )
+    .with_context(|| "mount overlayfs for root failed")?;
     for mount_point in mount_seq.iter() {
         let Some(mount_point) = mount_point else {
             continue;
@@ -238,6 +431,7 @@ pub fn mount_overlay(
             module_roots,
             &stock_root,
             mount_source,
+            options,
         ) {
             log::warn!(
                 "failed to mount overlay for child {}: {:#}, revert",
@@ -250,3 +444,58 @@ pub fn mount_overlay(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn flatten(layer: &str, stages: &HashMap<String, Vec<String>>, out: &mut Vec<String>) {
+        match stages.get(layer) {
+            Some(chunk) => chunk.iter().for_each(|l| flatten(l, stages, out)),
+            None => out.push(layer.to_string()),
+        }
+    }
+
+    #[test]
+    fn balanced_chunks_are_contiguous_and_bounded() {
+        for len in [2, 63, 64, 65, 128, 129, 500, 501, 1200] {
+            for max_layers in [2, 3, 64, 500] {
+                let chunks = balanced_chunks(len, max_layers);
+
+                assert_eq!(chunks.first().map(|r| r.start), Some(0));
+                assert_eq!(chunks.last().map(|r| r.end), Some(len));
+                for pair in chunks.windows(2) {
+                    assert_eq!(pair[0].end, pair[1].start);
+                }
+                for chunk in &chunks {
+                    assert!(!chunk.is_empty());
+                    assert!(chunk.len() <= max_layers);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stacking_preserves_layer_order() {
+        for (len, max_layers) in [(501, 500), (1200, 500), (777, 64), (65, 64), (40, 2)] {
+            let layers: Vec<String> = (0..len).map(|i| format!("layer{i}")).collect();
+            let mut stages = HashMap::new();
+
+            let top = stack_rounds(layers.clone(), max_layers, |chunk| {
+                assert!(chunk.len() <= max_layers);
+                let name = format!("stage{}", stages.len());
+                stages.insert(name.clone(), chunk.to_vec());
+                Ok(name)
+            })
+            .unwrap();
+
+            assert!(top.len() <= max_layers);
+
+            let mut flattened = Vec::new();
+            top.iter().for_each(|l| flatten(l, &stages, &mut flattened));
+            assert_eq!(flattened, layers);
+        }
+    }
+}
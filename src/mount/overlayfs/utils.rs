@@ -1,24 +1,26 @@
 // Copyright 2026 https://github.com/KernelSU-Modules-Repo/meta-overlayfs and https://github.com/bmax121/APatch
 
+use std::os::fd::{AsFd, BorrowedFd};
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use std::{fs, os::unix::fs::PermissionsExt, path::Path};
-use std::{io::Read, os::fd::AsFd};
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use anyhow::{Context, Result};
-use flate2::read::GzDecoder;
+use anyhow::{Context, Result, bail};
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use loopdev::LoopControl;
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use rustix::mount::{MountFlags, UnmountFlags, mount, unmount};
 use rustix::{
     fs::CWD,
+    io::read,
     mount::{
         FsMountFlags, FsOpenFlags, MountAttrFlags, MoveMountFlags, fsconfig_create,
-        fsconfig_set_string, fsmount, fsopen, move_mount,
+        fsconfig_set_flag, fsconfig_set_string, fsmount, fsopen, move_mount,
     },
 };
 
+use crate::conf::config::OverlayOptions;
+
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub fn mount_ext4<P>(source: P, target: P) -> Result<()>
 where
@@ -74,30 +76,6 @@ where
     Ok(())
 }
 
-pub fn is_overlay_supported() -> Result<bool> {
-    let file = fs::File::open("/proc/config.gz")?;
-
-    let mut config = String::new();
-    let mut decoder = GzDecoder::new(file);
-    decoder.read_to_string(&mut config)?;
-
-    for i in config.lines() {
-        if i.starts_with("#") {
-            continue;
-        }
-
-        let Some((k, v)) = i.split_once('=') else {
-            continue;
-        };
-
-        if k.trim() == "CONFIG_OVERLAY_FS" && v.trim() == "y" {
-            return Ok(true);
-        }
-    }
-
-    Ok(false)
-}
-
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub fn umount_dir(src: impl AsRef<Path>) -> Result<()> {
     unmount(src.as_ref(), UnmountFlags::empty())
@@ -105,12 +83,48 @@ pub fn umount_dir(src: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
+fn mount_attr_flags(options: &OverlayOptions) -> MountAttrFlags {
+    let mut flags = MountAttrFlags::empty();
+    if options.nosuid == Some(true) {
+        flags |= MountAttrFlags::MOUNT_ATTR_NOSUID;
+    }
+    if options.nodev == Some(true) {
+        flags |= MountAttrFlags::MOUNT_ATTR_NODEV;
+    }
+    if options.noexec == Some(true) {
+        flags |= MountAttrFlags::MOUNT_ATTR_NOEXEC;
+    }
+    if options.noatime == Some(true) {
+        flags |= MountAttrFlags::MOUNT_ATTR_NOATIME;
+    }
+    flags
+}
+
+fn kernel_messages(fs: BorrowedFd<'_>) -> String {
+    let mut messages = Vec::new();
+    let mut buf = [0u8; 1024];
+    while let Ok(len) = read(fs, &mut buf) {
+        if len == 0 {
+            break;
+        }
+        messages.push(String::from_utf8_lossy(&buf[..len]).trim().to_string());
+    }
+
+    if messages.is_empty() {
+        String::new()
+    } else {
+        format!(" (kernel: {})", messages.join("; "))
+    }
+}
+
 pub fn fs<S, P>(
     upperdir: Option<String>,
     workdir: Option<String>,
-    lowerdir_config: String,
+    lower_dirs: &[String],
+    append_layers: bool,
     source: S,
     dest: P,
+    options: &OverlayOptions,
 ) -> Result<()>
 where
     S: ToString,
@@ -118,18 +132,51 @@ where
 {
     let fs = fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC).context("Failed to fsopen overlay")?;
     let fs = fs.as_fd();
-    fsconfig_set_string(fs, "lowerdir", &lowerdir_config)
-        .context("Failed to fsconfig set string lowerdir with {lowerdir_config}")?;
+    if append_layers {
+        for lower in lower_dirs {
+            fsconfig_set_string(fs, "lowerdir+", lower)
+                .with_context(|| format!("Failed to fsconfig set string lowerdir+ with {lower}"))?;
+        }
+    } else {
+        let lowerdir_config = lower_dirs.join(":");
+        fsconfig_set_string(fs, "lowerdir", &lowerdir_config)
+            .context("Failed to fsconfig set string lowerdir with {lowerdir_config}")?;
+    }
     if let (Some(upperdir), Some(workdir)) = (&upperdir, &workdir) {
         fsconfig_set_string(fs, "upperdir", upperdir)
             .context("Failed to fsconfig set string upperdir with {upperdir}")?;
         fsconfig_set_string(fs, "workdir", workdir)
             .context("Failed to fsconfig set string workdir with {workdir}")?;
     }
+    for (key, value) in options.fs_params() {
+        if key == "volatile" && upperdir.is_none() {
+            log::debug!("skip overlay option volatile without upperdir");
+            continue;
+        }
+        let result = match &value {
+            Some(value) => fsconfig_set_string(fs, key, value),
+            None => fsconfig_set_flag(fs, key),
+        };
+        if let Err(e) = result {
+            bail!(
+                "overlay option {}{} rejected by kernel: {}{}",
+                key,
+                value.map(|v| format!("={v}")).unwrap_or_default(),
+                e,
+                kernel_messages(fs)
+            );
+        }
+    }
     fsconfig_set_string(fs, "source", source.to_string())
         .context("Failed to fsconfig set string source with {source}")?;
-    fsconfig_create(fs).context("Failed to fsconfig create new fs")?;
-    let mount = fsmount(fs, FsMountFlags::FSMOUNT_CLOEXEC, MountAttrFlags::empty())
+    if let Err(e) = fsconfig_create(fs) {
+        bail!(
+            "Failed to fsconfig create new fs: {}{}",
+            e,
+            kernel_messages(fs)
+        );
+    }
+    let mount = fsmount(fs, FsMountFlags::FSMOUNT_CLOEXEC, mount_attr_flags(options))
         .context("Failed to mount")?;
     move_mount(
         mount.as_fd(),
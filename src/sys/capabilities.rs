@@ -0,0 +1,259 @@
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::{
+    ffi::CString,
+    os::fd::AsFd,
+    path::{Path, PathBuf},
+};
+use std::{fs, sync::OnceLock};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use anyhow::Result;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use loopdev::LoopControl;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use rustix::{
+    fs::CWD,
+    mount::{
+        FsOpenFlags, MountFlags, OpenTreeFlags, UnmountFlags, fsconfig_set_string, fsopen, mount,
+        open_tree, unmount,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::core::state::RuntimeState;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::{
+    conf::config::OverlayOptions,
+    defs,
+    mount::overlayfs::{overlayfs::OVL_MAX_STACK, utils as overlay_utils},
+    sys::mount::{is_mounted, mount_tmpfs},
+    utils,
+};
+
+static CAPABILITIES: OnceLock<Capabilities> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub filesystems: Vec<String>,
+    pub overlay_listed: bool,
+    pub overlay_mount: Option<bool>,
+    pub tmpfs_xattr: Option<bool>,
+    pub fsopen: bool,
+    pub lowerdir_append: bool,
+    pub max_layers: Option<usize>,
+    pub open_tree: bool,
+    pub loop_control: bool,
+    pub erofs: bool,
+}
+
+impl Capabilities {
+    pub fn overlay_supported(&self) -> bool {
+        self.overlay_mount.unwrap_or(self.overlay_listed)
+    }
+
+    pub fn tmpfs_xattr_supported(&self) -> bool {
+        self.tmpfs_xattr.unwrap_or(false)
+    }
+}
+
+pub fn init() -> &'static Capabilities {
+    CAPABILITIES.get_or_init(probe)
+}
+
+pub fn get() -> &'static Capabilities {
+    CAPABILITIES.get_or_init(load_cached)
+}
+
+fn load_cached() -> Capabilities {
+    match RuntimeState::load() {
+        Ok(state) if state.timestamp != 0 && !state.capabilities.filesystems.is_empty() => {
+            state.capabilities
+        }
+        _ => {
+            log::debug!("No capabilities recorded at boot, using a non-mounting probe");
+            probe_passive()
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn probe() -> Capabilities {
+    let mut caps = probe_passive();
+
+    let scratch = Path::new(defs::RUN_DIR).join("probe");
+    if let Err(e) = probe_scratch(&scratch, &mut caps) {
+        log::warn!("Capability probe scratch setup failed: {:#}", e);
+    }
+    cleanup_scratch(&scratch);
+
+    log::debug!("kernel capabilities: {:?}", caps);
+
+    caps
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn probe() -> Capabilities {
+    probe_passive()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn probe_passive() -> Capabilities {
+    let filesystems = read_filesystems();
+    let listed = |name: &str| filesystems.iter().any(|fs| fs == name);
+
+    Capabilities {
+        overlay_listed: listed("overlay"),
+        erofs: listed("erofs"),
+        fsopen: fsopen("tmpfs", FsOpenFlags::FSOPEN_CLOEXEC).is_ok(),
+        lowerdir_append: probe_lowerdir_append(),
+        open_tree: open_tree(CWD, "/", OpenTreeFlags::OPEN_TREE_CLOEXEC).is_ok(),
+        loop_control: LoopControl::open().is_ok(),
+        filesystems,
+        ..Default::default()
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn probe_passive() -> Capabilities {
+    let filesystems = read_filesystems();
+    let listed = |name: &str| filesystems.iter().any(|fs| fs == name);
+
+    Capabilities {
+        overlay_listed: listed("overlay"),
+        erofs: listed("erofs"),
+        filesystems,
+        ..Default::default()
+    }
+}
+
+fn read_filesystems() -> Vec<String> {
+    fs::read_to_string("/proc/filesystems")
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| line.split_whitespace().last())
+                .map(|fs| fs.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn probe_lowerdir_append() -> bool {
+    fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC)
+        .is_ok_and(|fs| fsconfig_set_string(fs.as_fd(), "lowerdir+", "/").is_ok())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn probe_scratch(scratch: &Path, caps: &mut Capabilities) -> Result<()> {
+    cleanup_scratch(scratch);
+    mount_tmpfs(scratch, "hybrid_probe")?;
+
+    caps.tmpfs_xattr = Some(utils::is_trusted_xattr_supported(scratch));
+
+    let lowers: Vec<PathBuf> = ["lower0", "lower1"]
+        .iter()
+        .map(|name| scratch.join(name))
+        .collect();
+    let merged = scratch.join("merged");
+    for dir in lowers.iter().chain([&merged]) {
+        fs::create_dir_all(dir)?;
+    }
+
+    let options = format!(
+        "lowerdir={}",
+        lowers
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(":")
+    );
+
+    match mount(
+        "overlay",
+        &merged,
+        "overlay",
+        MountFlags::RDONLY,
+        Some(CString::new(options)?.as_c_str()),
+    ) {
+        Ok(_) => {
+            caps.overlay_mount = Some(true);
+            let _ = unmount(&merged, UnmountFlags::DETACH);
+            caps.max_layers = probe_layer_limit(scratch, &merged, caps)?;
+        }
+        Err(e) => {
+            log::debug!("trial overlay mount failed: {}", e);
+            caps.overlay_mount = Some(false);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn probe_layer_limit(scratch: &Path, merged: &Path, caps: &Capabilities) -> Result<Option<usize>> {
+    let layers_dir = scratch.join("layers");
+    let layers: Vec<String> = (0..OVL_MAX_STACK)
+        .map(|i| layers_dir.join(i.to_string()).display().to_string())
+        .collect();
+    for layer in &layers {
+        fs::create_dir_all(layer)?;
+    }
+
+    let try_mount = |count: usize| {
+        let lowers = &layers[..count];
+        let mounted = if caps.fsopen {
+            overlay_utils::fs(
+                None,
+                None,
+                lowers,
+                caps.lowerdir_append,
+                "hybrid_probe",
+                merged,
+                &OverlayOptions::default(),
+            )
+            .is_ok()
+        } else {
+            CString::new(format!("lowerdir={}", lowers.join(":"))).is_ok_and(|data| {
+                mount(
+                    "overlay",
+                    merged,
+                    "overlay",
+                    MountFlags::RDONLY,
+                    Some(data.as_c_str()),
+                )
+                .is_ok()
+            })
+        };
+        if mounted {
+            let _ = unmount(merged, UnmountFlags::DETACH);
+        }
+        mounted
+    };
+
+    if !try_mount(2) {
+        return Ok(None);
+    }
+
+    let (mut lo, mut hi) = (2, OVL_MAX_STACK);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if try_mount(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+
+    Ok(Some(lo))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn cleanup_scratch(scratch: &Path) {
+    if is_mounted(scratch) {
+        let _ = unmount(scratch, UnmountFlags::DETACH);
+    }
+    if scratch.exists() {
+        let _ = fs::remove_dir_all(scratch);
+    }
+}
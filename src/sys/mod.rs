@@ -1,2 +1,3 @@
+pub mod capabilities;
 pub mod mount;
 pub mod nuke;
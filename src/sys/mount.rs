@@ -1,10 +1,16 @@
-use std::{fs, path::Path, process::Command};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use anyhow::{Context, Result, bail};
 use procfs::process::Process;
-use rustix::mount::{MountFlags, mount};
+use rustix::mount::{
+    MountFlags, MountPropagationFlags, UnmountFlags, mount, mount_change, unmount,
+};
 
-use crate::utils::ensure_dir_exists;
+use crate::{defs, utils::ensure_dir_exists};
 
 pub fn detect_mount_source() -> String {
     if ksu::version().is_some() {
@@ -63,3 +69,42 @@ pub fn repair_image(image_path: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+pub fn isolate_stock_view(mount_source: &str, partitions: &[String]) -> Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to unshare mount namespace");
+    }
+    mount_change(
+        "/",
+        MountPropagationFlags::PRIVATE | MountPropagationFlags::REC,
+    )
+    .context("Failed to make / private")?;
+
+    let mut targets: Vec<PathBuf> = Process::myself()?
+        .mountinfo()?
+        .into_iter()
+        .filter(|m| {
+            m.mount_point.iter().nth(1).is_some_and(|root| {
+                let root = root.to_string_lossy();
+                defs::BUILTIN_PARTITIONS.contains(&root.as_ref())
+                    || partitions.iter().any(|p| *p == root)
+            })
+        })
+        .filter(|m| {
+            m.mount_source.as_deref() == Some(mount_source)
+                || defs::IGNORE_UNOUNT_PARTITIONS
+                    .iter()
+                    .any(|p| m.mount_point == Path::new(p) && m.fs_type == "overlay")
+        })
+        .map(|m| m.mount_point)
+        .collect();
+    targets.sort_by_key(|t| std::cmp::Reverse(t.components().count()));
+
+    for target in targets {
+        if let Err(e) = unmount(&target, UnmountFlags::DETACH) {
+            log::debug!("Failed to detach {}: {}", target.display(), e);
+        }
+    }
+
+    Ok(())
+}
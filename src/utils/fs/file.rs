@@ -4,12 +4,13 @@ use std::{
     fs::{self, File, OpenOptions},
     io::Write,
     os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt, symlink},
-    path::Path,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result, bail};
 use rustix::fs::ioctl_ficlone;
+use sha2::{Digest, Sha256};
 use walkdir::WalkDir;
 
 use super::xattr::internal_copy_extended_attributes;
@@ -44,6 +45,27 @@ pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, content: C) -> Resu
     Ok(())
 }
 
+pub fn dead_absolute_symlink(path: &Path) -> Option<PathBuf> {
+    let target = fs::read_link(path).ok()?;
+    if target.is_absolute() && !target.exists() {
+        Some(target)
+    } else {
+        None
+    }
+}
+
+pub fn setid_mode(metadata: &fs::Metadata) -> Option<u32> {
+    let mode = metadata.mode();
+    (!metadata.is_symlink() && mode & (libc::S_ISUID | libc::S_ISGID) != 0).then_some(mode & 0o7777)
+}
+
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 pub fn ensure_dir_exists<T: AsRef<Path>>(dir: T) -> Result<()> {
     if !dir.as_ref().exists() {
         fs::create_dir_all(&dir)?;
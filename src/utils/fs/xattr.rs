@@ -1,13 +1,14 @@
 use std::path::Path;
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use std::{fs, io::Read, os::unix::ffi::OsStrExt};
+use std::{fs, os::unix::ffi::OsStrExt};
 
 use anyhow::{Context, Result};
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use extattr::{Flags as XattrFlags, lgetxattr, llistxattr, lsetxattr};
+use extattr::{Flags as XattrFlags, lgetxattr, llistxattr, lremovexattr, lsetxattr};
 
 const SELINUX_XATTR: &str = "security.selinux";
 const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+const PROBE_XATTR: &str = "trusted.hybrid_mount.probe";
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
 fn copy_extended_attributes(src: &Path, dst: &Path) -> Result<()> {
@@ -55,6 +56,42 @@ pub fn set_overlay_opaque<P: AsRef<Path>>(_path: P) -> Result<()> {
     unimplemented!();
 }
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn is_overlay_opaque<P: AsRef<Path>>(path: P) -> bool {
+    lgetxattr(path.as_ref(), OVERLAY_OPAQUE_XATTR).is_ok_and(|v| v.as_slice() == b"y")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn is_overlay_opaque<P: AsRef<Path>>(_path: P) -> bool {
+    unimplemented!();
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn has_overlay_xattr<P: AsRef<Path>>(path: P, key: &str) -> bool {
+    lgetxattr(path.as_ref(), format!("trusted.overlay.{key}")).is_ok()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn has_overlay_xattr<P: AsRef<Path>>(_path: P, _key: &str) -> bool {
+    unimplemented!();
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn strip_overlay_xattrs<P: AsRef<Path>>(path: P) -> Result<()> {
+    for name in llistxattr(path.as_ref())? {
+        let name_str = name.to_string_lossy();
+        if name_str.starts_with("trusted.overlay.") && name_str != OVERLAY_OPAQUE_XATTR {
+            lremovexattr(path.as_ref(), &name)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn strip_overlay_xattrs<P: AsRef<Path>>(_path: P) -> Result<()> {
+    unimplemented!();
+}
+
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub fn lsetfilecon<P: AsRef<Path>>(path: P, con: &str) -> Result<()> {
     if let Err(e) = lsetxattr(
@@ -91,34 +128,27 @@ pub fn lgetfilecon<P: AsRef<Path>>(_path: P) -> Result<String> {
     unimplemented!();
 }
 
-#[cfg(any(target_os = "linux", target_os = "android"))]
-pub fn is_overlay_xattr_supported() -> Result<bool> {
-    use flate2::read::GzDecoder;
-    let file = fs::File::open("/proc/config.gz")?;
-
-    let mut config = String::new();
-    let mut decoder = GzDecoder::new(file);
-    decoder.read_to_string(&mut config)?;
-
-    for i in config.lines() {
-        if i.starts_with("#") {
-            continue;
-        }
+pub fn selinux_enabled() -> bool {
+    Path::new("/sys/fs/selinux").exists()
+}
 
-        let Some((k, v)) = i.split_once('=') else {
-            continue;
-        };
+pub fn lacks_selinux_label<P: AsRef<Path>>(path: P) -> bool {
+    lgetfilecon(path).map_or(true, |con| con.is_empty())
+}
 
-        if k.trim() == "CONFIG_TMPFS_XATTR" && v.trim() == "y" {
-            return Ok(true);
-        }
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn is_trusted_xattr_supported<P: AsRef<Path>>(dir: P) -> bool {
+    let probe = dir.as_ref().join(".xattr_probe");
+    if fs::write(&probe, b"").is_err() {
+        return false;
     }
-
-    Ok(false)
+    let supported = lsetxattr(&probe, PROBE_XATTR, b"y", XattrFlags::empty()).is_ok();
+    let _ = fs::remove_file(&probe);
+    supported
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
-pub fn is_overlay_xattr_supported() -> Result<bool> {
+pub fn is_trusted_xattr_supported<P: AsRef<Path>>(_dir: P) -> bool {
     unimplemented!();
 }
 
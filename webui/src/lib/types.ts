@@ -6,10 +6,24 @@
 export interface ModuleRules {
   default_mode: MountMode;
   paths: Record<string, string>;
+  priority?: number;
 }
 
 export type OverlayMode = "tmpfs" | "ext4" | "erofs";
 
+export interface OverlayOptions {
+  redirect_dir?: string;
+  metacopy?: boolean;
+  index?: boolean;
+  xino?: string;
+  userxattr?: boolean;
+  volatile?: boolean;
+  nosuid?: boolean;
+  nodev?: boolean;
+  noexec?: boolean;
+  noatime?: boolean;
+}
+
 export interface AppConfig {
   moduledir: string;
   mountsource: string;
@@ -17,10 +31,16 @@ export interface AppConfig {
   overlay_mode: OverlayMode;
   disable_umount: boolean;
   allow_umount_coexistence: boolean;
+  module_order?: string[];
+  overlay_options?: OverlayOptions;
+  overlay_target_options?: Record<string, OverlayOptions>;
+  rw_partitions?: string[];
+  rw_max_mb?: number;
+  conflict_policy?: Record<string, string>;
   logfile?: string;
 }
 
-export type MountMode = "overlay" | "magic" | "ignore";
+export type MountMode = "overlay" | "magic" | "ignore" | "auto";
 
 export interface Module {
   id: string;